@@ -37,7 +37,7 @@ fn is_safe(report: &str, allow_violation: bool) -> bool {
     false
 }
 
-fn is_safe_levels(levels: &Vec<i32>) -> bool {
+fn is_safe_levels(levels: &[i32]) -> bool {
     // Assume there is atleast two levels in each report, otherwise this will panic
     // Would a report with only one level be considered safe or unsafe?
     let order = levels[0].cmp(&levels[1]);
@@ -58,5 +58,5 @@ fn is_safe_levels(levels: &Vec<i32>) -> bool {
         }
     }
 
-    return true;
+    true
 }
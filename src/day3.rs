@@ -1,6 +1,6 @@
-use std::fs::read_to_string;
+use std::{fmt, fs::read_to_string, ops::Range};
 
-pub fn sum_of_valid_muls(filename: &str, check_do_dont: bool) -> i32 {
+pub fn sum_of_valid_muls(filename: &str, check_do_dont: bool) -> Result<i64, OverflowError> {
     let input = read_to_string(filename).unwrap_or(String::from(""));
 
     let mut do_it = true;
@@ -10,7 +10,7 @@ pub fn sum_of_valid_muls(filename: &str, check_do_dont: bool) -> i32 {
         // Check if there are any more possible instructions starting with "mul("
         let next_mul_start = match input[current..].find("mul(") {
             Some(idx) => idx,
-            None => return sum,
+            None => return Ok(sum),
         };
 
         if check_do_dont {
@@ -25,9 +25,17 @@ pub fn sum_of_valid_muls(filename: &str, check_do_dont: bool) -> i32 {
 
         if let Some(mul) = get_next_mul(&input[current..]) {
             // Found a valid mul instruction
+            let span = current + mul.start..current + mul.end + 1;
             current += mul.end + 1;
             if do_it {
-                sum += mul.op_1 * mul.op_2;
+                sum = mul
+                    .op_1
+                    .checked_mul(mul.op_2)
+                    .and_then(|product| sum.checked_add(product))
+                    .ok_or_else(|| OverflowError {
+                        instr: input[span.clone()].to_string(),
+                        span,
+                    })?;
             }
         } else {
             // The last mul wasn't valid, check the next possible candidate
@@ -35,13 +43,21 @@ pub fn sum_of_valid_muls(filename: &str, check_do_dont: bool) -> i32 {
         }
     }
 
-    sum
+    Ok(sum)
+}
+
+// The mul instruction at span (byte offsets into the input) overflowed the sum
+#[derive(Debug)]
+pub struct OverflowError {
+    pub span: Range<usize>,
+    pub instr: String,
 }
 
 struct MulInstr {
+    start: usize,
     end: usize,
-    op_1: i32,
-    op_2: i32,
+    op_1: i64,
+    op_2: i64,
 }
 
 enum DoDontInstr {
@@ -54,7 +70,7 @@ fn get_most_recent_do_dont(input: &str) -> DoDontInstr {
     let do_start = input.rfind("do()");
     let dont_start = input.rfind("don't()");
 
-    return match (do_start, dont_start) {
+    match (do_start, dont_start) {
         (Some(_), None) => DoDontInstr::Do,
         (None, Some(_)) => DoDontInstr::Dont,
         (Some(do_idx), Some(dont_idx)) => {
@@ -65,7 +81,7 @@ fn get_most_recent_do_dont(input: &str) -> DoDontInstr {
             }
         }
         (None, None) => DoDontInstr::NotFound,
-    };
+    }
 }
 
 fn get_next_mul(input: &str) -> Option<MulInstr> {
@@ -78,8 +94,23 @@ fn get_next_mul(input: &str) -> Option<MulInstr> {
     let ops_1 = ops.next()?;
     let ops_2 = ops.next()?;
 
-    let op_1 = ops_1.parse::<i32>().ok()?;
-    let op_2 = ops_2.parse::<i32>().ok()?;
+    let op_1 = ops_1.parse::<i64>().ok()?;
+    let op_2 = ops_2.parse::<i64>().ok()?;
 
-    Some(MulInstr { end, op_1, op_2 })
+    Some(MulInstr {
+        start,
+        end,
+        op_1,
+        op_2,
+    })
+}
+
+impl fmt::Display for OverflowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "overflow in '{}' at {}..{}",
+            self.instr, self.span.start, self.span.end
+        )
+    }
 }
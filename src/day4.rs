@@ -1,115 +1,1028 @@
-use std::fs::read_to_string;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    fs::read_to_string,
+    time::Instant,
+};
+
+use crate::rng::XorShift;
 
 // XMAS
 
-pub fn xmas_count(filename: &str) -> usize {
+// An empty (or missing) file is a valid grid without any words, so it counts 0
+pub fn xmas_count(filename: &str) -> Result<usize, GridError> {
+    Ok(xmas_matches(filename)?.1.len())
+}
+
+// The grid showing only the letters of each XMAS found, see OutputMode
+pub fn xmas_output(filename: &str, mode: OutputMode) -> Result<String, GridError> {
+    let (search, matches) = xmas_matches(filename)?;
+    Ok(search.render(&matches, mode))
+}
+
+fn xmas_matches(filename: &str) -> Result<(WordSearch, Vec<Match>), GridError> {
     let input = read_to_string(filename).unwrap_or(String::from(""));
 
-    let grid = input
-        .lines()
-        .map(|l| l.chars().collect::<Vec<_>>())
-        .collect::<Vec<_>>();
+    let search = WordSearch::parse(&input)?;
+    let matches = search.find(&["XMAS"], &Direction::ALL);
+    Ok((search, matches))
+}
+
+// Search engines for WordSearch::find_with, all of them find the same matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    // Check every direction from every start letter, one character at a time
+    Scalar,
+    // One bitboard per letter, matches all start positions of a word in a
+    // direction at once using shifts and ANDs. Much faster on very large grids.
+    BitParallel,
+    // Builds an Aho-Corasick automaton over all words and streams every row,
+    // column and diagonal through it once. Best for long word lists.
+    AhoCorasick,
+}
+
+// Word search puzzle over a grid of characters. Words are found along straight
+// lines in any of the eight directions. Rows may have different lengths, every
+// lookup is bounds checked against the length of its own row.
+pub struct WordSearch {
+    grid: Vec<Vec<char>>,
+}
+
+impl WordSearch {
+    pub fn new(grid: Vec<Vec<char>>) -> WordSearch {
+        WordSearch { grid }
+    }
+
+    // Parse a rectangular grid, one row per line. Trailing blank lines are
+    // ignored and an empty input gives an empty grid. Rows of different lengths
+    // (including blank lines in between rows) are an error.
+    pub fn parse(input: &str) -> Result<WordSearch, GridError> {
+        let search = WordSearch::parse_ragged(input);
+
+        let width = search.grid.first().map_or(0, |row| row.len());
+        match search.grid.iter().position(|row| row.len() != width) {
+            Some(y) => Err(GridError::Ragged {
+                line: y + 1,
+                expected: width,
+                found: search.grid[y].len(),
+            }),
+            None => Ok(search),
+        }
+    }
+
+    // Parse a grid which may have rows of different lengths, trailing blank
+    // lines are ignored
+    pub fn parse_ragged(input: &str) -> WordSearch {
+        let mut grid = input
+            .lines()
+            .map(|l| l.chars().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        while grid.last().is_some_and(|row| row.is_empty()) {
+            grid.pop();
+        }
+
+        WordSearch::new(grid)
+    }
+
+    // Find every occurrence of every word in any of the given directions.
+    // Matches are ordered by start position (row by row), then word, then direction.
+    pub fn find(&self, words: &[&str], dirs: &[Direction]) -> Vec<Match> {
+        self.find_with(words, dirs, Engine::Scalar)
+    }
+
+    // Same as find, but with a choice of search engine
+    pub fn find_with(&self, words: &[&str], dirs: &[Direction], engine: Engine) -> Vec<Match> {
+        let words = words
+            .iter()
+            .map(|w| w.chars().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        match engine {
+            Engine::Scalar => self.find_scalar(&words, dirs),
+            Engine::BitParallel => self.find_bit_parallel(&words, dirs),
+            Engine::AhoCorasick => self.find_aho_corasick(&words, dirs),
+        }
+    }
+
+    fn find_scalar(&self, words: &[Vec<char>], dirs: &[Direction]) -> Vec<Match> {
+        let mut matches = vec![];
+        for (y, row) in self.grid.iter().enumerate() {
+            for (x, &c) in row.iter().enumerate() {
+                for word in words.iter() {
+                    // Cheap check for the first letter before walking any direction
+                    if word.first() != Some(&c) {
+                        continue;
+                    }
+
+                    for &dir in dirs {
+                        if self.spells(word, x, y, dir) {
+                            matches.push(Match {
+                                word: word.iter().collect(),
+                                x,
+                                y,
+                                dir,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        matches
+    }
+
+    // Find every position where one of the patterns fits completely inside the
+    // grid and all of its fixed cells match. Pass the result of
+    // Pattern::symmetries to also find rotated and mirrored shapes.
+    pub fn find_patterns(&self, patterns: &[Pattern]) -> Vec<PatternMatch> {
+        let mut matches = vec![];
+        for (y, row) in self.grid.iter().enumerate() {
+            for x in 0..row.len() {
+                for (i, pattern) in patterns.iter().enumerate() {
+                    if self.fits(pattern, x, y) {
+                        matches.push(PatternMatch {
+                            x,
+                            y,
+                            pattern: i,
+                            cells: pattern
+                                .fixed_cells()
+                                .map(|(px, py, _)| (x + px, y + py))
+                                .collect(),
+                        });
+                    }
+                }
+            }
+        }
+
+        matches
+    }
+
+    fn fits(&self, pattern: &Pattern, x: usize, y: usize) -> bool {
+        let (x, y) = (x as i64, y as i64);
+        let right = x + pattern.width() as i64 - 1;
+
+        // The whole template has to be inside the grid, including wildcards
+        let inside = (0..pattern.height() as i64).all(|py| self.get(right, y + py).is_some());
+
+        pattern.height() > 0
+            && inside
+            && pattern
+                .fixed_cells()
+                .all(|(px, py, c)| self.get(x + px as i64, y + py as i64) == Some(c))
+    }
+
+    fn spells(&self, word: &[char], x: usize, y: usize, dir: Direction) -> bool {
+        let (dir_x, dir_y) = dir.delta();
+
+        word.iter().enumerate().all(|(i, &c)| {
+            let i = i as i64;
+            self.get(x as i64 + dir_x * i, y as i64 + dir_y * i) == Some(c)
+        })
+    }
+
+    // Character at the given coordinates or None if outside of the grid
+    fn get(&self, x: i64, y: i64) -> Option<char> {
+        if x < 0 || y < 0 {
+            return None;
+        }
+
+        self.grid.get(y as usize)?.get(x as usize).copied()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GridError {
+    // Line (1-based) has a different length than the first line
+    Ragged {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for GridError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GridError::Ragged {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "grid is not rectangular, line {line} has {found} characters instead of {expected}"
+            ),
+        }
+    }
+}
+
+// A word found in the grid, starting at (x, y) and read in direction dir
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub word: String,
+    pub x: usize,
+    pub y: usize,
+    pub dir: Direction,
+}
+
+impl GridMatch for Match {
+    fn cells(&self) -> Vec<(usize, usize)> {
+        let (dir_x, dir_y) = self.dir.delta();
+
+        (0..self.word.chars().count() as i64)
+            .map(|i| {
+                (
+                    (self.x as i64 + dir_x * i) as usize,
+                    (self.y as i64 + dir_y * i) as usize,
+                )
+            })
+            .collect()
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"word\":{},\"x\":{},\"y\":{},\"direction\":\"{}\",\"cells\":{}}}",
+            json_string(&self.word),
+            self.x,
+            self.y,
+            self.dir.name(),
+            json_cells(&self.cells())
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Right,
+    Left,
+    Down,
+    Up,
+    DownRight,
+    UpLeft,
+    DownLeft,
+    UpRight,
+}
+
+impl Direction {
+    pub const ORTHOGONAL: [Direction; 4] = [
+        Direction::Right,
+        Direction::Left,
+        Direction::Down,
+        Direction::Up,
+    ];
+
+    pub const DIAGONAL: [Direction; 4] = [
+        Direction::DownRight,
+        Direction::UpLeft,
+        Direction::DownLeft,
+        Direction::UpRight,
+    ];
+
+    // Directions reading left to right / top to bottom, as words are usually written
+    pub const FORWARD: [Direction; 4] = [
+        Direction::Right,
+        Direction::Down,
+        Direction::DownRight,
+        Direction::UpRight,
+    ];
+
+    // The forward directions reversed, for words written backwards
+    pub const BACKWARD: [Direction; 4] = [
+        Direction::Left,
+        Direction::Up,
+        Direction::UpLeft,
+        Direction::DownLeft,
+    ];
+
+    pub const ALL: [Direction; 8] = [
+        Direction::Right,
+        Direction::Left,
+        Direction::Down,
+        Direction::Up,
+        Direction::DownRight,
+        Direction::UpLeft,
+        Direction::DownLeft,
+        Direction::UpRight,
+    ];
+
+    // Step in x and y when moving one letter in this direction
+    pub fn delta(&self) -> (i64, i64) {
+        match self {
+            Direction::Right => (1, 0),
+            Direction::Left => (-1, 0),
+            Direction::Down => (0, 1),
+            Direction::Up => (0, -1),
+            Direction::DownRight => (1, 1),
+            Direction::UpLeft => (-1, -1),
+            Direction::DownLeft => (-1, 1),
+            Direction::UpRight => (1, -1),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Direction::Right => "right",
+            Direction::Left => "left",
+            Direction::Down => "down",
+            Direction::Up => "up",
+            Direction::DownRight => "down-right",
+            Direction::UpLeft => "up-left",
+            Direction::DownLeft => "down-left",
+            Direction::UpRight => "up-right",
+        }
+    }
+
+    pub fn reversed(&self) -> Direction {
+        match self {
+            Direction::Right => Direction::Left,
+            Direction::Left => Direction::Right,
+            Direction::Down => Direction::Up,
+            Direction::Up => Direction::Down,
+            Direction::DownRight => Direction::UpLeft,
+            Direction::UpLeft => Direction::DownRight,
+            Direction::DownLeft => Direction::UpRight,
+            Direction::UpRight => Direction::DownLeft,
+        }
+    }
+}
+
+// X-MAS (two MAS in form of X)
+
+// Same empty input policy as xmas_count
+pub fn x_mas_count(filename: &str) -> Result<usize, GridError> {
+    Ok(x_mas_matches(filename)?.1.len())
+}
+
+// The grid showing only the letters of each X-MAS found, see OutputMode
+pub fn x_mas_output(filename: &str, mode: OutputMode) -> Result<String, GridError> {
+    let (search, matches) = x_mas_matches(filename)?;
+    Ok(search.render(&matches, mode))
+}
+
+fn x_mas_matches(filename: &str) -> Result<(WordSearch, Vec<PatternMatch>), GridError> {
+    let input = read_to_string(filename).unwrap_or(String::from(""));
+
+    // Both diagonals spell MAS, forwards or backwards, which are exactly the
+    // rotations of this template
+    let x_mas = Pattern::parse("M.S / .A. / M.S");
+
+    let search = WordSearch::parse(&input)?;
+    let matches = search.find_patterns(&x_mas.symmetries());
+    Ok((search, matches))
+}
+
+// Small 2D template of characters, None cells match any character
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Pattern {
+    rows: Vec<Vec<Option<char>>>,
+}
+
+impl Pattern {
+    pub const WILDCARD: char = '.';
+
+    // Rows are separated by newlines or '/', whitespace around rows is ignored
+    // and short rows are padded with wildcards, e.g. "M.S / .A. / M.S"
+    pub fn parse(text: &str) -> Pattern {
+        let rows = text
+            .split(['\n', '/'])
+            .map(|row| row.trim())
+            .filter(|row| !row.is_empty())
+            .map(|row| {
+                row.chars()
+                    .map(|c| (c != Pattern::WILDCARD).then_some(c))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        Pattern {
+            rows: rows
+                .into_iter()
+                .map(|mut row| {
+                    row.resize(width, None);
+                    row
+                })
+                .collect(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.rows.first().map_or(0, |row| row.len())
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    // Rotated by 90 degrees clockwise
+    pub fn rotated(&self) -> Pattern {
+        Pattern {
+            rows: (0..self.width())
+                .map(|x| (0..self.height()).rev().map(|y| self.rows[y][x]).collect())
+                .collect(),
+        }
+    }
+
+    // Mirrored left to right
+    pub fn reflected(&self) -> Pattern {
+        Pattern {
+            rows: self
+                .rows
+                .iter()
+                .map(|row| row.iter().rev().copied().collect())
+                .collect(),
+        }
+    }
+
+    // All distinct rotations, starting with the pattern itself
+    pub fn rotations(&self) -> Vec<Pattern> {
+        let mut rotations: Vec<Pattern> = vec![];
+        let mut current = self.clone();
+        for _ in 0..4 {
+            let next = current.rotated();
+            if !rotations.contains(&current) {
+                rotations.push(current);
+            }
+            current = next;
+        }
+
+        rotations
+    }
+
+    // All distinct rotations and reflections, starting with the pattern itself
+    pub fn symmetries(&self) -> Vec<Pattern> {
+        let mut symmetries = self.rotations();
+        for reflection in self.reflected().rotations() {
+            if !symmetries.contains(&reflection) {
+                symmetries.push(reflection);
+            }
+        }
+
+        symmetries
+    }
+
+    // Offsets of the cells that have to match a specific character
+    fn fixed_cells(&self) -> impl Iterator<Item = (usize, usize, char)> + '_ {
+        self.rows.iter().enumerate().flat_map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .filter_map(move |(x, cell)| cell.map(|c| (x, y, c)))
+        })
+    }
+}
+
+// A pattern found in the grid with its top left corner at (x, y). Pattern is the
+// index into the searched patterns and cells are the grid coordinates of the
+// pattern's non-wildcard cells.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternMatch {
+    pub x: usize,
+    pub y: usize,
+    pub pattern: usize,
+    pub cells: Vec<(usize, usize)>,
+}
+
+impl GridMatch for PatternMatch {
+    fn cells(&self) -> Vec<(usize, usize)> {
+        self.cells.clone()
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"x\":{},\"y\":{},\"pattern\":{},\"cells\":{}}}",
+            self.x,
+            self.y,
+            self.pattern,
+            json_cells(&self.cells)
+        )
+    }
+}
+
+// Highlighted output of matches
+
+// Anything found in the grid that covers a set of cells
+pub trait GridMatch {
+    // Grid coordinates of every matched letter
+    fn cells(&self) -> Vec<(usize, usize)>;
+    fn to_json(&self) -> String;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    // Letters which are not part of any match are replaced by '.', like in the
+    // puzzle description
+    Plain,
+    // Like Plain, but each match is coloured with ANSI escape codes. Overlapping
+    // matches get different colours and shared letters are drawn bold.
+    Ansi,
+    // JSON array of all matches
+    Json,
+}
+
+// Foreground colours: red, green, yellow, blue, magenta, cyan
+const ANSI_COLOURS: [u8; 6] = [31, 32, 33, 34, 35, 36];
+const ANSI_BOLD: &str = "\x1b[1m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+impl WordSearch {
+    pub fn render<M: GridMatch>(&self, matches: &[M], mode: OutputMode) -> String {
+        match mode {
+            OutputMode::Plain => self.render_plain(matches),
+            OutputMode::Ansi => self.render_ansi(matches),
+            OutputMode::Json => format!(
+                "[{}]\n",
+                matches
+                    .iter()
+                    .map(|m| m.to_json())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        }
+    }
+
+    fn render_plain<M: GridMatch>(&self, matches: &[M]) -> String {
+        let covered = self.covering_matches(matches);
+
+        self.render_cells(|x, y, c| match covered.get(&(x, y)) {
+            Some(_) => c.to_string(),
+            None => String::from("."),
+        })
+    }
+
+    fn render_ansi<M: GridMatch>(&self, matches: &[M]) -> String {
+        let covered = self.covering_matches(matches);
+
+        // Greedy colouring, each match gets the first colour which isn't used by
+        // an earlier match it overlaps with
+        let mut colours: Vec<usize> = vec![];
+        for (i, m) in matches.iter().enumerate() {
+            let taken = m
+                .cells()
+                .iter()
+                .flat_map(|cell| covered[cell].iter())
+                .filter(|&&other| other < i)
+                .map(|&other| colours[other])
+                .collect::<Vec<_>>();
+
+            let colour = (0..ANSI_COLOURS.len())
+                .find(|c| !taken.contains(c))
+                .unwrap_or(i % ANSI_COLOURS.len());
+            colours.push(colour);
+        }
+
+        self.render_cells(|x, y, c| match covered.get(&(x, y)) {
+            Some(owners) => format!(
+                "{}\x1b[{}m{c}{ANSI_RESET}",
+                if owners.len() > 1 { ANSI_BOLD } else { "" },
+                ANSI_COLOURS[colours[owners[0]]]
+            ),
+            None => String::from("."),
+        })
+    }
+
+    // Indices of all matches covering each cell
+    fn covering_matches<M: GridMatch>(&self, matches: &[M]) -> HashMap<(usize, usize), Vec<usize>> {
+        let mut covered = HashMap::<(usize, usize), Vec<usize>>::new();
+        for (i, m) in matches.iter().enumerate() {
+            for cell in m.cells() {
+                covered.entry(cell).or_default().push(i);
+            }
+        }
+
+        covered
+    }
+
+    fn render_cells(&self, render: impl Fn(usize, usize, char) -> String) -> String {
+        let mut output = String::new();
+        for (y, row) in self.grid.iter().enumerate() {
+            for (x, &c) in row.iter().enumerate() {
+                output += &render(x, y, c);
+            }
+            output.push('\n');
+        }
+
+        output
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json += "\\\"",
+            '\\' => json += "\\\\",
+            c if c.is_control() => json += &format!("\\u{:04x}", c as u32),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+
+    json
+}
+
+fn json_cells(cells: &[(usize, usize)]) -> String {
+    format!(
+        "[{}]",
+        cells
+            .iter()
+            .map(|(x, y)| format!("[{x},{y}]"))
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+// Matches given as (y, x, word index, direction index) in the order of the
+// scalar search, for the engines that find them in another order
+fn in_search_order(
+    mut found: Vec<(usize, usize, usize, usize)>,
+    words: &[Vec<char>],
+    dirs: &[Direction],
+) -> Vec<Match> {
+    found.sort_unstable();
+    found
+        .into_iter()
+        .map(|(y, x, w, d)| Match {
+            word: words[w].iter().collect(),
+            x,
+            y,
+            dir: dirs[d],
+        })
+        .collect()
+}
+
+// Bit-parallel search for large grids
+
+impl WordSearch {
+    fn find_bit_parallel(&self, words: &[Vec<char>], dirs: &[Direction]) -> Vec<Match> {
+        let width = self.grid.iter().map(|row| row.len()).max().unwrap_or(0);
+
+        // Only the letters of the words are needed, one pass over the grid each
+        let mut boards = HashMap::<char, BitBoard>::new();
+        for &letter in words.iter().flatten() {
+            if boards.contains_key(&letter) {
+                continue;
+            }
+
+            boards.insert(letter, BitBoard::of_letter(&self.grid, width, letter));
+        }
+
+        // (y, x, word index, direction index), see in_search_order
+        let mut found = vec![];
+        for (w, word) in words.iter().enumerate() {
+            for (d, &dir) in dirs.iter().enumerate() {
+                let (dir_x, dir_y) = dir.delta();
 
-    let mut count = 0;
-    for (y, row) in grid.iter().enumerate() {
-        for (x, &c) in row.iter().enumerate() {
-            if c == 'X' {
-                count += spells_xmas_count(&grid, x as i32, y as i32);
+                // A bit is still set after the loop if the word starts there
+                let Some(mut starts) = word.first().and_then(|c| boards.get(c)).cloned() else {
+                    continue;
+                };
+                for (i, c) in word.iter().enumerate().skip(1) {
+                    let i = i as i64;
+                    match boards.get(c) {
+                        Some(board) => starts.and_shifted(board, dir_x * i, dir_y * i),
+                        None => starts.clear(),
+                    }
+                }
+
+                found.extend(starts.ones().map(|(x, y)| (y, x, w, d)));
             }
         }
+
+        in_search_order(found, words, dirs)
     }
+}
 
-    count
+// One bit per grid cell, each row padded to whole u64 words
+#[derive(Clone)]
+struct BitBoard {
+    height: usize,
+    stride: usize,
+    bits: Vec<u64>,
 }
 
-fn spells_xmas_count(grid: &Vec<Vec<char>>, x: i32, y: i32) -> usize {
-    const DIRS: [i32; 3] = [-1, 0, 1];
+impl BitBoard {
+    fn new(width: usize, height: usize) -> BitBoard {
+        let stride = width.div_ceil(64);
+        BitBoard {
+            height,
+            stride,
+            bits: vec![0; stride * height],
+        }
+    }
+
+    // Board with the cells set where the grid holds letter
+    fn of_letter(grid: &[Vec<char>], width: usize, letter: char) -> BitBoard {
+        let mut board = BitBoard::new(width, grid.len());
+        for (y, row) in grid.iter().enumerate() {
+            for (w, chunk) in row.chunks(64).enumerate() {
+                board.bits[y * board.stride + w] = chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0, |word, (i, &c)| word | (((c == letter) as u64) << i));
+            }
+        }
+
+        board
+    }
+
+    fn clear(&mut self) {
+        self.bits.fill(0);
+    }
+
+    // self[y][x] &= other[y + dy][x + dx], cells outside of other count as unset
+    fn and_shifted(&mut self, other: &BitBoard, dx: i64, dy: i64) {
+        for y in 0..self.height {
+            let row = &mut self.bits[y * self.stride..(y + 1) * self.stride];
 
-    let mut count = 0;
-    for dir_x in DIRS {
-        for dir_y in DIRS {
-            if dir_x == 0 && dir_y == 0 {
+            let src_y = y as i64 + dy;
+            if src_y < 0 || src_y >= other.height as i64 {
+                row.fill(0);
                 continue;
             }
 
-            if spells_xmas(&grid, x, y, dir_x, dir_y) {
-                count += 1;
+            let src_start = src_y as usize * other.stride;
+            let src = &other.bits[src_start..src_start + other.stride];
+            for (w, word) in row.iter_mut().enumerate() {
+                *word &= shifted_word(src, w, dx);
             }
         }
     }
 
-    count
+    // Coordinates of all set bits, row by row
+    fn ones(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.bits.iter().enumerate().flat_map(move |(i, &word)| {
+            let (y, w) = (i / self.stride, i % self.stride);
+            let mut word = word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                Some((w * 64 + bit, y))
+            })
+        })
+    }
 }
 
-fn spells_xmas(grid: &Vec<Vec<char>>, x: i32, y: i32, dir_x: i32, dir_y: i32) -> bool {
-    let grid_len_x = grid[0].len() as i32;
-    let grid_len_y = grid.len() as i32;
+// Word w of the row after shifting it so that bit x holds the source bit x + dx
+fn shifted_word(src: &[u64], w: usize, dx: i64) -> u64 {
+    let get = |i: i64| {
+        if i < 0 || i >= src.len() as i64 {
+            0
+        } else {
+            src[i as usize]
+        }
+    };
+
+    let w = w as i64;
+    let (q, r) = (dx.div_euclid(64), dx.rem_euclid(64) as u32);
+    if r == 0 {
+        get(w + q)
+    } else {
+        (get(w + q) >> r) | (get(w + q + 1) << (64 - r))
+    }
+}
+
+// Multi-word search with an Aho-Corasick automaton
+
+impl WordSearch {
+    // Streams every line of the grid in each direction through one automaton
+    // over all words, so the grid is read once per direction regardless of the
+    // number of words
+    fn find_aho_corasick(&self, words: &[Vec<char>], dirs: &[Direction]) -> Vec<Match> {
+        let automaton = AhoCorasick::new(words);
+        let width = self.grid.iter().map(|row| row.len()).max().unwrap_or(0) as i64;
+        let height = self.grid.len() as i64;
+        let in_box = |x: i64, y: i64| x >= 0 && y >= 0 && x < width && y < height;
 
-    const MAS: &str = "MAS";
-    const MAS_LEN: i32 = MAS.len() as i32;
+        // (y, x, word index, direction index), see in_search_order
+        let mut found = vec![];
+        for (d, &dir) in dirs.iter().enumerate() {
+            let (dir_x, dir_y) = dir.delta();
 
-    if y + MAS_LEN * dir_y < 0
-        || y + MAS_LEN * dir_y >= grid_len_y
-        || x + MAS_LEN * dir_x < 0
-        || x + MAS_LEN * dir_x >= grid_len_x
-    {
-        return false;
+            // A line starts at every cell whose predecessor is outside the grid
+            for start_y in 0..height {
+                for start_x in 0..width {
+                    if in_box(start_x - dir_x, start_y - dir_y) {
+                        continue;
+                    }
+
+                    let mut state = 0;
+                    let (mut x, mut y) = (start_x, start_y);
+                    while in_box(x, y) {
+                        match self.get(x, y) {
+                            Some(c) => state = automaton.next(state, c),
+                            // Gap in a ragged grid, no word can span it
+                            None => state = 0,
+                        }
+
+                        // Words end at (x, y), step back to where they start
+                        for &w in automaton.outputs[state].iter() {
+                            let back = words[w].len() as i64 - 1;
+                            let (word_x, word_y) = (x - dir_x * back, y - dir_y * back);
+                            found.push((word_y as usize, word_x as usize, w, d));
+                        }
+
+                        (x, y) = (x + dir_x, y + dir_y);
+                    }
+                }
+            }
+        }
+
+        in_search_order(found, words, dirs)
     }
+}
 
-    for (u, c) in MAS.chars().enumerate() {
-        let i = u as i32;
-        if grid[(y + dir_y * (i + 1)) as usize][(x + dir_x * (i + 1)) as usize] != c {
-            return false;
+// Aho-Corasick automaton compiled to a full transition table, states are
+// indices and 0 is the root. Characters are mapped to their index in the
+// alphabet of all words plus one, class 0 is any character not in a word.
+struct AhoCorasick {
+    alphabet: Vec<char>,
+    delta: Vec<usize>,
+    // Indices of all words ending in this state, including through failure links
+    outputs: Vec<Vec<usize>>,
+}
+
+impl AhoCorasick {
+    fn new(words: &[Vec<char>]) -> AhoCorasick {
+        let mut alphabet = words.iter().flatten().copied().collect::<Vec<_>>();
+        alphabet.sort_unstable();
+        alphabet.dedup();
+
+        let classes = alphabet.len() + 1;
+        let mut automaton = AhoCorasick {
+            alphabet,
+            delta: vec![usize::MAX; classes],
+            outputs: vec![vec![]],
+        };
+
+        // Build the trie, missing transitions are usize::MAX for now
+        for (w, word) in words.iter().enumerate() {
+            // Empty words never match, same as in the other engines
+            if word.is_empty() {
+                continue;
+            }
+
+            let mut state = 0;
+            for &c in word {
+                let i = state * classes + automaton.class(c);
+                if automaton.delta[i] == usize::MAX {
+                    automaton.delta[i] = automaton.outputs.len();
+                    automaton.delta.extend(vec![usize::MAX; classes]);
+                    automaton.outputs.push(vec![]);
+                }
+                state = automaton.delta[i];
+            }
+            automaton.outputs[state].push(w);
+        }
+
+        // Breadth first over the trie, filling in the missing transitions with
+        // the ones of the failure state, which is always done already
+        let mut queue = VecDeque::new();
+        for class in 0..classes {
+            match automaton.delta[class] {
+                usize::MAX => automaton.delta[class] = 0,
+                child => queue.push_back((child, 0)),
+            }
+        }
+
+        while let Some((state, fail)) = queue.pop_front() {
+            let inherited = automaton.outputs[fail].clone();
+            automaton.outputs[state].extend(inherited);
+
+            for class in 0..classes {
+                let i = state * classes + class;
+                let fail_next = automaton.delta[fail * classes + class];
+                match automaton.delta[i] {
+                    usize::MAX => automaton.delta[i] = fail_next,
+                    child => queue.push_back((child, fail_next)),
+                }
+            }
         }
+
+        automaton
+    }
+
+    fn class(&self, c: char) -> usize {
+        self.alphabet.binary_search(&c).map_or(0, |i| i + 1)
     }
 
-    true
+    fn next(&self, state: usize, c: char) -> usize {
+        self.delta[state * (self.alphabet.len() + 1) + self.class(c)]
+    }
 }
 
-// X-MAS (two MAS in form of X)
+// Benchmark of the search engines on a generated square grid
 
-pub fn x_mas_count(filename: &str) -> usize {
-    let input = read_to_string(filename).unwrap_or(String::from(""));
+// Runs every engine on a pseudo random grid of the letters X, M, A and S, once
+// for XMAS and once for a few hundred random words. Checks that the engines
+// agree and reports their run times.
+pub fn benchmark(size: usize) -> String {
+    let mut rng = XorShift::new();
+    let search = WordSearch::new(
+        (0..size)
+            .map(|_| (0..size).map(|_| rng.letter("XMAS")).collect())
+            .collect(),
+    );
 
-    let grid = input
-        .lines()
-        .map(|l| l.chars().collect::<Vec<_>>())
+    let many_words = (0..300)
+        .map(|_| {
+            let len = 4 + rng.next() as usize % 5;
+            (0..len).map(|_| rng.letter("XMAS")).collect::<String>()
+        })
         .collect::<Vec<_>>();
+    let many_words = many_words.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let mut report = String::new();
+    for (name, words) in [("XMAS", vec!["XMAS"]), ("300 words", many_words)] {
+        report += &format!("{name} search on a {size}x{size} grid\n");
 
-    let mut count = 0;
-    for (y, row) in grid.iter().enumerate() {
-        for (x, &c) in row.iter().enumerate() {
-            // The center character for our X shape
-            if c == 'A' {
-                count += spells_x_mas_count(&grid, x as i32, y as i32);
+        let mut expected: Option<Vec<Match>> = None;
+        for engine in [Engine::Scalar, Engine::BitParallel, Engine::AhoCorasick] {
+            let start = Instant::now();
+            let matches = search.find_with(&words, &Direction::ALL, engine);
+            let elapsed = start.elapsed();
+
+            report += &format!("{engine:?}: {} matches in {elapsed:?}\n", matches.len());
+            match &expected {
+                Some(expected) if *expected != matches => {
+                    report += &format!("{engine:?}: matches differ from {:?}\n", Engine::Scalar)
+                }
+                Some(_) => (),
+                None => expected = Some(matches),
             }
         }
     }
 
-    count
+    report
 }
 
-fn spells_x_mas_count(grid: &Vec<Vec<char>>, x: i32, y: i32) -> usize {
-    // Check if both diagonals spell MAS
-    if spells_mas(&grid, x, y, -1, -1) && spells_mas(&grid, x, y, -1, 1) {
-        return 1;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Random grids of a few letters, so words are found often. Widths go past
+    // 64 to cover the padding of the bitboard rows, ragged grids get random
+    // row lengths.
+    fn random_searches(ragged: bool) -> Vec<WordSearch> {
+        let mut rng = XorShift::new();
+        (0..40)
+            .map(|_| {
+                let width = 1 + rng.next() as usize % 80;
+                let height = 1 + rng.next() as usize % 20;
+                let grid = (0..height)
+                    .map(|_| {
+                        let len = if ragged {
+                            rng.next() as usize % (width + 1)
+                        } else {
+                            width
+                        };
+                        (0..len).map(|_| rng.letter("XMAS")).collect()
+                    })
+                    .collect();
+                WordSearch::new(grid)
+            })
+            .collect()
     }
 
-    0
-}
+    // Single letters, palindromes, words that are prefixes or suffixes of
+    // others and a letter that is never in the grid
+    const WORDS: [&str; 9] = [
+        "XMAS", "X", "SAS", "XMASX", "MAS", "AS", "SAMX", "XX", "XMQ",
+    ];
 
-fn spells_mas(grid: &Vec<Vec<char>>, x: i32, y: i32, dir_x: i32, dir_y: i32) -> bool {
-    let grid_len_x = grid[0].len() as i32;
-    let grid_len_y = grid.len() as i32;
+    fn assert_engine_matches_scalar(engine: Engine) {
+        let dir_sets: [&[Direction]; 4] = [
+            &Direction::ALL,
+            &Direction::FORWARD,
+            &Direction::DIAGONAL,
+            &[Direction::UpLeft],
+        ];
 
-    // Needs to be atleast 1 from each border to form an X shape
-    if y == 0 || y == grid_len_y - 1 || x == 0 || x == grid_len_x - 1 {
-        return false;
+        for ragged in [false, true] {
+            for search in random_searches(ragged) {
+                for dirs in dir_sets {
+                    for words in [&WORDS[..], &WORDS[..1]] {
+                        assert_eq!(
+                            search.find_with(words, dirs, engine),
+                            search.find_with(words, dirs, Engine::Scalar),
+                            "{engine:?} on {:?} with {words:?} in {dirs:?}",
+                            search.grid
+                        );
+                    }
+                }
+            }
+        }
     }
 
-    let c1 = grid[(y + dir_y) as usize][(x + dir_x) as usize];
-    let c2 = grid[(y - dir_y) as usize][(x - dir_x) as usize];
+    #[test]
+    fn bit_parallel_finds_the_scalar_matches() {
+        assert_engine_matches_scalar(Engine::BitParallel);
+    }
 
-    // Forwards or backwards
-    (c1 == 'M' && c2 == 'S') || (c1 == 'S' && c2 == 'M')
+    #[test]
+    fn aho_corasick_finds_the_scalar_matches() {
+        assert_engine_matches_scalar(Engine::AhoCorasick);
+    }
 }
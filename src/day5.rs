@@ -1,94 +1,830 @@
-use std::{collections::HashMap, fs::read_to_string};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::read_to_string,
+    time::Instant,
+};
 
 use itertools::Itertools;
 
-pub fn sum_of_correct_update_middle_pages(filename_rules: &str, filename_updates: &str) -> i32 {
-    let rules = parse_rules(filename_rules);
-    let updates = parse_updates(filename_updates);
+use crate::rng::XorShift;
+
+pub fn sum_of_correct_update_middle_pages(
+    input: &Input,
+    policy: MiddlePolicy,
+) -> Result<i32, OrderError> {
+    let (rules, updates) = parse_input(input)?;
 
     updates
         .iter()
         .filter(|update| is_correctly_ordered(update, &rules))
-        .map(|update| update[update.len() / 2])
+        .map(|update| middle_page(update, policy))
         .sum()
 }
 
-pub fn sum_of_reordered_middle_pages(filename_rules: &str, filename_updates: &str) -> i32 {
-    let rules = parse_rules(filename_rules);
-    let updates = parse_updates(filename_updates);
+// Fails for the first update whose pages can't be ordered because of a cycle in
+// the rules between them
+pub fn sum_of_reordered_middle_pages(
+    input: &Input,
+    policy: MiddlePolicy,
+) -> Result<i32, OrderError> {
+    let (rules, updates) = parse_input(input)?;
 
     updates
         .iter()
         .filter(|update| !is_correctly_ordered(update, &rules))
-        .map(|update| reordered_update(update, &rules))
-        .map(|update| update[update.len() / 2])
+        .map(|update| middle_page(&reordered_update(update, &rules)?, policy))
         .sum()
 }
 
-fn parse_rules(filename: &str) -> HashMap<i32, Vec<i32>> {
-    read_to_string(filename)
-        .unwrap_or(String::from(""))
-        .lines()
-        .fold(HashMap::<i32, Vec<i32>>::new(), |mut map, line| {
-            let mut parts = line.split("|").map(|e| e.parse::<i32>().unwrap());
-            match parts.next_tuple() {
-                Some((l, r)) => map.entry(l).or_insert(vec![]).push(r),
-                None => (),
+// What the middle page of an update with an even number of pages is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MiddlePolicy {
+    // Fail with OrderError::EvenLength, the puzzle only has odd lengths
+    Error,
+    // The first of the two middle pages
+    LowerMiddle,
+    // The second of the two middle pages
+    UpperMiddle,
+    // Average of the two middle pages, rounded down
+    Average,
+}
+
+// Parsed updates are never empty, an empty update is an OrderError::EvenLength
+pub fn middle_page(update: &[i32], policy: MiddlePolicy) -> Result<i32, OrderError> {
+    let half = update.len() / 2;
+    if update.len() % 2 == 1 {
+        return Ok(update[half]);
+    }
+
+    match (policy, update.is_empty()) {
+        (MiddlePolicy::Error, _) | (_, true) => Err(OrderError::EvenLength {
+            update: update.to_vec(),
+        }),
+        (MiddlePolicy::LowerMiddle, false) => Ok(update[half - 1]),
+        (MiddlePolicy::UpperMiddle, false) => Ok(update[half]),
+        (MiddlePolicy::Average, false) => {
+            Ok((update[half - 1] as i64 + update[half] as i64).div_euclid(2) as i32)
+        }
+    }
+}
+
+// Where to read the page ordering rules and the updates from
+pub enum Input<'a> {
+    // The puzzle input as given: the rules, a blank line, then the updates
+    Combined(&'a str),
+    // Rules and updates in separate files
+    Split { rules: &'a str, updates: &'a str },
+}
+
+// Page ordering rules "before|after", with constant time lookup of a rule
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    rules: HashSet<(i32, i32)>,
+    // Pages mapped to all pages that have to be printed after them, for walking
+    // the rules as a graph
+    successors: HashMap<i32, Vec<i32>>,
+    // Dense bit matrix for the pages low..=high, bit (a - low) * span + (b - low)
+    // is set if a|b. Empty if the pages are spread too far, lookups then fall
+    // back to the hash set.
+    low: i32,
+    high: i32,
+    matrix: Vec<u64>,
+}
+
+// Largest range of page numbers for the bit matrix, 512 KiB
+const MAX_MATRIX_SPAN: usize = 2048;
+
+impl RuleSet {
+    pub fn new() -> RuleSet {
+        RuleSet::default()
+    }
+
+    pub fn insert(&mut self, before: i32, after: i32) {
+        if !self.rules.insert((before, after)) {
+            return;
+        }
+        self.successors.entry(before).or_default().push(after);
+
+        let (low, high) = match self.rules.len() {
+            1 => (before.min(after), before.max(after)),
+            _ => (
+                self.low.min(before).min(after),
+                self.high.max(before).max(after),
+            ),
+        };
+
+        if (low, high) == (self.low, self.high) && !self.matrix.is_empty() {
+            self.set_bit(before, after);
+        } else {
+            // The range grew, lay out the matrix again
+            (self.low, self.high) = (low, high);
+            self.matrix.clear();
+            if self.span() <= MAX_MATRIX_SPAN {
+                self.matrix = vec![0; (self.span() * self.span()).div_ceil(64)];
+                for (a, b) in self.rules.clone() {
+                    self.set_bit(a, b);
+                }
             }
-            map
+        }
+    }
+
+    // True if there is a rule that a has to be printed before b
+    pub fn must_precede(&self, a: i32, b: i32) -> bool {
+        if self.matrix.is_empty() {
+            return self.rules.contains(&(a, b));
+        }
+
+        match self.bit(a, b) {
+            Some(bit) => self.matrix[bit / 64] & (1 << (bit % 64)) != 0,
+            None => false,
+        }
+    }
+
+    fn span(&self) -> usize {
+        (self.high as i64 - self.low as i64 + 1) as usize
+    }
+
+    // Index into the matrix or None if a page is outside of its range
+    fn bit(&self, a: i32, b: i32) -> Option<usize> {
+        let range = self.low..=self.high;
+        if !range.contains(&a) || !range.contains(&b) {
+            return None;
+        }
+
+        Some((a - self.low) as usize * self.span() + (b - self.low) as usize)
+    }
+
+    fn set_bit(&mut self, a: i32, b: i32) {
+        if let Some(bit) = self.bit(a, b) {
+            self.matrix[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    // Order of two pages by the rule between them, Equal if there is none. Not
+    // transitive, so it only sorts correctly if the rules between the sorted
+    // pages are complete (as they are for each update in the puzzle), use
+    // comparator_for otherwise.
+    pub fn compare(&self, a: &i32, b: &i32) -> Ordering {
+        if self.must_precede(*a, *b) {
+            Ordering::Less
+        } else if self.must_precede(*b, *a) {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    }
+
+    // Total order for sort_by over the pages of update, ranking each page by
+    // its position in the reordered update. Pages that aren't part of the
+    // update come last, by page number. Fails if the rules between the pages
+    // form a cycle.
+    pub fn comparator_for(
+        &self,
+        update: &[i32],
+    ) -> Result<impl Fn(&i32, &i32) -> Ordering, OrderError> {
+        let ranks = reordered_update(update, self)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, page)| (page, i))
+            .collect::<HashMap<_, _>>();
+
+        Ok(move |a: &i32, b: &i32| {
+            let rank = |page: &i32| (ranks.get(page).copied().unwrap_or(usize::MAX), *page);
+            rank(a).cmp(&rank(b))
         })
+    }
+
+    // All pages that have to be printed after page
+    pub fn successors(&self, page: i32) -> &[i32] {
+        self.successors.get(&page).map_or(&[], |pages| pages)
+    }
+
+    // Every page that appears in a rule, in ascending order
+    pub fn pages(&self) -> Vec<i32> {
+        self.rules
+            .iter()
+            .flat_map(|&(before, after)| [before, after])
+            .unique()
+            .sorted()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderError {
+    Parse(ParseError),
+    // The rules between the pages of the update form a cycle, listed in rule
+    // order so that each page has to come before the next and the last before
+    // the first
+    Cycle { update: Vec<i32>, cycle: Vec<i32> },
+    // The update has no single middle page and MiddlePolicy::Error was used
+    EvenLength { update: Vec<i32> },
+}
+
+// Line numbers are 1-based and count from the start of the file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    // A combined input has no blank line between the rules and the updates
+    MissingSeparator,
+    InvalidRule { line: usize, text: String },
+    InvalidUpdate { line: usize, text: String },
+    // A blank line between two updates
+    EmptyUpdate { line: usize },
+    DuplicatePage { line: usize, page: i32 },
+}
+
+// Missing or empty files have no rules and no updates
+pub fn parse_input(input: &Input) -> Result<(RuleSet, Vec<Vec<i32>>), ParseError> {
+    match input {
+        Input::Combined(filename) => {
+            let text = read_to_string(filename).unwrap_or(String::from(""));
+            if text.trim().is_empty() {
+                return Ok((RuleSet::new(), vec![]));
+            }
+
+            let lines = text.lines().collect::<Vec<_>>();
+            let separator = lines
+                .iter()
+                .position(|line| line.trim().is_empty())
+                .ok_or(ParseError::MissingSeparator)?;
+            // Several blank lines still only separate the rules from the updates
+            let updates_start = lines[separator..]
+                .iter()
+                .position(|line| !line.trim().is_empty())
+                .map_or(lines.len(), |i| separator + i);
+
+            Ok((
+                parse_rules(&lines[..separator], 1)?,
+                parse_updates(&lines[updates_start..], updates_start + 1)?,
+            ))
+        }
+        Input::Split { rules, updates } => {
+            let rules = read_to_string(rules).unwrap_or(String::from(""));
+            let updates = read_to_string(updates).unwrap_or(String::from(""));
+
+            Ok((
+                parse_rules(&rules.lines().collect::<Vec<_>>(), 1)?,
+                parse_updates(&updates.lines().collect::<Vec<_>>(), 1)?,
+            ))
+        }
+    }
+}
+
+// Lines of "<page>|<page>", first_line is the line number of lines[0]
+fn parse_rules(lines: &[&str], first_line: usize) -> Result<RuleSet, ParseError> {
+    let mut rules = RuleSet::new();
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (l, r) = line
+            .split("|")
+            .map(|e| e.trim().parse::<i32>().ok())
+            .collect_tuple()
+            .and_then(|(l, r)| Some((l?, r?)))
+            .ok_or_else(|| ParseError::InvalidRule {
+                line: first_line + i,
+                text: line.to_string(),
+            })?;
+        rules.insert(l, r);
+    }
+
+    Ok(rules)
 }
 
-fn parse_updates(filename: &str) -> Vec<Vec<i32>> {
-    read_to_string(filename)
-        .unwrap_or(String::from(""))
-        .lines()
-        .map(|line| {
-            line.split(",")
-                .map(|e| e.parse::<i32>().unwrap())
-                .collect::<Vec<_>>()
+// Lines of comma separated pages, first_line is the line number of lines[0].
+// Trailing blank lines are ignored.
+fn parse_updates(lines: &[&str], first_line: usize) -> Result<Vec<Vec<i32>>, ParseError> {
+    let end = lines
+        .iter()
+        .rposition(|line| !line.trim().is_empty())
+        .map_or(0, |i| i + 1);
+
+    lines[..end]
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let line_nr = first_line + i;
+            if line.trim().is_empty() {
+                return Err(ParseError::EmptyUpdate { line: line_nr });
+            }
+
+            let update = line
+                .split(",")
+                .map(|e| e.trim().parse::<i32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| ParseError::InvalidUpdate {
+                    line: line_nr,
+                    text: line.to_string(),
+                })?;
+
+            match update.iter().duplicates().next() {
+                Some(&page) => Err(ParseError::DuplicatePage {
+                    line: line_nr,
+                    page,
+                }),
+                None => Ok(update),
+            }
         })
-        .collect::<Vec<_>>()
+        .collect()
+}
+
+// Topological sort (Kahn's algorithm) of the rules restricted to the pages of
+// the update. Fails if the rules between the pages form a cycle.
+//
+// If the rules allow several orders (see count_orderings), whenever several
+// pages are ready the one that comes first in the original update is taken.
+// Pages keep their original relative order wherever the rules allow it, and
+// the result is the first ordering list_orderings returns.
+fn reordered_update(update: &[i32], rules: &RuleSet) -> Result<Vec<i32>, OrderError> {
+    let mut in_degree = in_degrees(update, rules);
+
+    let mut placed = vec![false; update.len()];
+    let mut new_update = Vec::with_capacity(update.len());
+    while let Some(i) = (0..update.len()).find(|&i| !placed[i] && in_degree[i] == 0) {
+        placed[i] = true;
+        new_update.push(update[i]);
+
+        for (j, &other) in update.iter().enumerate() {
+            if !placed[j] && rules.must_precede(update[i], other) {
+                in_degree[j] -= 1;
+            }
+        }
+    }
+
+    // Anything left over is on or behind a cycle
+    if new_update.len() < update.len() {
+        let left = (0..update.len())
+            .filter(|&i| !placed[i])
+            .map(|i| update[i])
+            .collect::<Vec<_>>();
+
+        return Err(OrderError::Cycle {
+            update: update.to_vec(),
+            cycle: find_update_cycle(&left, rules).unwrap_or(left),
+        });
+    }
+
+    Ok(new_update)
+}
+
+// Number of pages in the update that have to come before each page
+fn in_degrees(update: &[i32], rules: &RuleSet) -> Vec<usize> {
+    let mut in_degree = vec![0; update.len()];
+    for (i, &page) in update.iter().enumerate() {
+        for (j, &other) in update.iter().enumerate() {
+            if i != j && rules.must_precede(page, other) {
+                in_degree[j] += 1;
+            }
+        }
+    }
+
+    in_degree
+}
+
+fn is_correctly_ordered(update: &[i32], rules: &RuleSet) -> bool {
+    for (i, &page) in update.iter().enumerate() {
+        for &following_page in update.iter().skip(i + 1) {
+            if rules.must_precede(following_page, page) {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+// Violation report
+
+// The rule "before|after" is broken because after is printed before it, at
+// positions (0-based) in the update
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub before: i32,
+    pub after: i32,
+    pub before_pos: usize,
+    pub after_pos: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateReport {
+    pub update: Vec<i32>,
+    pub violations: Vec<Violation>,
+    // What reordered_update makes of the update, the update itself if it has
+    // no violations
+    pub corrected: Result<Vec<i32>, OrderError>,
 }
 
-fn reordered_update(update: &Vec<i32>, rules: &HashMap<i32, Vec<i32>>) -> Vec<i32> {
-    let mut new_update = update.clone();
+// Report of every update, correctly ordered or not. Only parse errors fail the
+// whole report, cycles are reported per update.
+pub fn violation_report(input: &Input) -> Result<Vec<UpdateReport>, OrderError> {
+    let (rules, updates) = parse_input(input)?;
 
-    while !is_correctly_ordered(&new_update, rules) {
-        reorder_single(&mut new_update, rules);
+    Ok(updates
+        .into_iter()
+        .map(|update| UpdateReport {
+            violations: violations(&update, &rules),
+            corrected: reordered_update(&update, &rules),
+            update,
+        })
+        .collect())
+}
+
+// Every broken rule of the update, ordered by the positions of the pages
+pub fn violations(update: &[i32], rules: &RuleSet) -> Vec<Violation> {
+    let mut violations = vec![];
+    for (i, &page) in update.iter().enumerate() {
+        for (j, &following_page) in update.iter().enumerate().skip(i + 1) {
+            if rules.must_precede(following_page, page) {
+                violations.push(Violation {
+                    before: following_page,
+                    after: page,
+                    before_pos: j,
+                    after_pos: i,
+                });
+            }
+        }
     }
 
-    new_update
+    violations
+}
+
+// All valid orderings of an update
+
+// True if the rules between the update's pages allow exactly one order. That
+// is the case if every page of the sorted update has a rule with the next one.
+pub fn is_ordering_unique(update: &[i32], rules: &RuleSet) -> Result<bool, OrderError> {
+    Ok(reordered_update(update, rules)?
+        .windows(2)
+        .all(|pair| rules.must_precede(pair[0], pair[1])))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrderingCount {
+    pub count: usize,
+    // Counting stopped at the cap, there may be more orderings
+    pub capped: bool,
 }
 
-fn reorder_single(update: &mut Vec<i32>, rules: &HashMap<i32, Vec<i32>>) {
-    for i in 0..update.len() {
-        let page = update[i];
-        for j in i + 1..update.len() {
-            let following_page = update[j];
-            match rules.get(&following_page) {
-                Some(deps) => {
-                    if deps.contains(&page) {
-                        update.swap(i, j);
-                        return;
-                    }
+// Number of valid orderings (linear extensions of the rules between the
+// update's pages), counting at most cap of them
+pub fn count_orderings(
+    update: &[i32],
+    rules: &RuleSet,
+    cap: usize,
+) -> Result<OrderingCount, OrderError> {
+    let mut count = 0;
+    let capped = for_each_ordering(update, rules, cap, |_| count += 1)?;
+
+    Ok(OrderingCount { count, capped })
+}
+
+// Up to cap valid orderings. Earlier pages of the original update are placed
+// first where possible, so the first ordering is the one reordered_update gives.
+pub fn list_orderings(
+    update: &[i32],
+    rules: &RuleSet,
+    cap: usize,
+) -> Result<Vec<Vec<i32>>, OrderError> {
+    let mut orderings = vec![];
+    for_each_ordering(update, rules, cap, |ordering| {
+        orderings.push(ordering.to_vec())
+    })?;
+
+    Ok(orderings)
+}
+
+// Calls found for up to cap orderings, returns true if it stopped at the cap
+fn for_each_ordering(
+    update: &[i32],
+    rules: &RuleSet,
+    cap: usize,
+    mut found: impl FnMut(&[i32]),
+) -> Result<bool, OrderError> {
+    // Also rejects rule cycles, which have no orderings at all
+    reordered_update(update, rules)?;
+
+    // Search for one more than the cap to know if there are more orderings
+    let mut search = OrderingSearch {
+        update,
+        rules,
+        in_degree: in_degrees(update, rules),
+        placed: vec![false; update.len()],
+        prefix: Vec::with_capacity(update.len()),
+        remaining: cap.saturating_add(1),
+    };
+
+    let mut count = 0;
+    search.extend(&mut |ordering: &[i32]| {
+        count += 1;
+        if count <= cap {
+            found(ordering);
+        }
+    });
+
+    Ok(count > cap)
+}
+
+// Backtracking over all choices of ready pages in Kahn's algorithm
+struct OrderingSearch<'a> {
+    update: &'a [i32],
+    rules: &'a RuleSet,
+    in_degree: Vec<usize>,
+    placed: Vec<bool>,
+    prefix: Vec<i32>,
+    // Number of orderings still allowed before hitting the cap
+    remaining: usize,
+}
+
+impl OrderingSearch<'_> {
+    fn extend(&mut self, found: &mut impl FnMut(&[i32])) {
+        if self.remaining == 0 {
+            return;
+        }
+        if self.prefix.len() == self.update.len() {
+            found(&self.prefix);
+            self.remaining -= 1;
+            return;
+        }
+
+        for i in 0..self.update.len() {
+            if self.placed[i] || self.in_degree[i] > 0 {
+                continue;
+            }
+
+            self.place(i, true);
+            self.extend(found);
+            self.place(i, false);
+
+            if self.remaining == 0 {
+                return;
+            }
+        }
+    }
+
+    // Place page i at the end of the prefix or take it back off
+    fn place(&mut self, i: usize, placed: bool) {
+        self.placed[i] = placed;
+        if placed {
+            self.prefix.push(self.update[i]);
+        } else {
+            self.prefix.pop();
+        }
+
+        for j in 0..self.update.len() {
+            if j != i && self.rules.must_precede(self.update[i], self.update[j]) {
+                if placed {
+                    self.in_degree[j] -= 1;
+                } else {
+                    self.in_degree[j] += 1;
                 }
-                None => (),
             }
         }
     }
 }
 
-fn is_correctly_ordered(update: &Vec<i32>, rules: &HashMap<i32, Vec<i32>>) -> bool {
+// Rule analysis
+
+// Any cycle in the complete set of rules. Puzzle inputs usually have cycles
+// globally, only the rules between the pages of a single update need to be
+// acyclic, see find_update_cycle.
+pub fn find_cycle(rules: &RuleSet) -> Option<Vec<i32>> {
+    find_update_cycle(&rules.pages(), rules)
+}
+
+// Any cycle in the rules between the given pages, ignoring rules which involve
+// other pages
+pub fn find_update_cycle(update: &[i32], rules: &RuleSet) -> Option<Vec<i32>> {
+    let pages = update.iter().copied().collect::<HashSet<_>>();
+    let mut done = HashSet::new();
+
+    for &page in update {
+        let mut path = vec![];
+        if let Some(cycle) = cycle_from(page, &pages, rules, &mut path, &mut done) {
+            return Some(cycle);
+        }
+    }
+
+    None
+}
+
+// Depth first search, path holds the pages currently being visited and a rule
+// back to one of them closes a cycle. A rule like "5|5" is ignored, as it is
+// when ordering an update.
+fn cycle_from(
+    page: i32,
+    pages: &HashSet<i32>,
+    rules: &RuleSet,
+    path: &mut Vec<i32>,
+    done: &mut HashSet<i32>,
+) -> Option<Vec<i32>> {
+    if let Some(start) = path.iter().position(|&p| p == page) {
+        return Some(path[start..].to_vec());
+    }
+    if done.contains(&page) {
+        return None;
+    }
+
+    path.push(page);
+    for &next in rules.successors(page) {
+        if next == page || !pages.contains(&next) {
+            continue;
+        }
+        if let Some(cycle) = cycle_from(next, pages, rules, path, done) {
+            return Some(cycle);
+        }
+    }
+    path.pop();
+    done.insert(page);
+
+    None
+}
+
+impl From<ParseError> for OrderError {
+    fn from(err: ParseError) -> OrderError {
+        OrderError::Parse(err)
+    }
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderError::Parse(err) => write!(f, "{err}"),
+            OrderError::Cycle { update, cycle } => write!(
+                f,
+                "update {} can't be ordered, its rules contain the cycle {}|{}",
+                update.iter().join(","),
+                cycle.iter().join("|"),
+                cycle[0]
+            ),
+            OrderError::EvenLength { update } => write!(
+                f,
+                "update {} has an even number of pages and no single middle page",
+                update.iter().join(",")
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::MissingSeparator => write!(
+                f,
+                "missing blank line between the ordering rules and the updates"
+            ),
+            ParseError::InvalidRule { line, text } => {
+                write!(f, "line {line}: expected a rule like 47|53, found '{text}'")
+            }
+            ParseError::InvalidUpdate { line, text } => write!(
+                f,
+                "line {line}: expected comma separated pages, found '{text}'"
+            ),
+            ParseError::EmptyUpdate { line } => write!(f, "line {line}: empty update"),
+            ParseError::DuplicatePage { line, page } => {
+                write!(f, "line {line}: page {page} appears more than once")
+            }
+        }
+    }
+}
+
+impl fmt::Display for UpdateReport {
+    // Positions are shown 1-based
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let update = self.update.iter().join(",");
+        if self.violations.is_empty() {
+            return writeln!(f, "{update}: correctly ordered");
+        }
+
+        writeln!(f, "{update}: {} broken rule(s)", self.violations.len())?;
+        for v in self.violations.iter() {
+            writeln!(
+                f,
+                "    {}|{}: {} at {}, {} at {}",
+                v.before,
+                v.after,
+                v.after,
+                v.after_pos + 1,
+                v.before,
+                v.before_pos + 1
+            )?;
+        }
+
+        match &self.corrected {
+            Ok(corrected) => writeln!(f, "    corrected: {}", corrected.iter().join(",")),
+            Err(err) => writeln!(f, "    {err}"),
+        }
+    }
+}
+
+// Benchmark of the rule lookup on generated rules and updates
+
+// Pages 10 to 99 with a rule for every pair, like the puzzle input but for
+// more pages, and random updates of 5 to 23 pages. Compares checking and
+// sorting the updates with RuleSet against a map of pages to lists of
+// following pages.
+pub fn benchmark(updates: usize) -> String {
+    let mut rng = XorShift::new();
+
+    let mut order = (10..100).collect::<Vec<i32>>();
+    rng.shuffle(&mut order);
+
+    let mut rule_set = RuleSet::new();
+    let mut rule_lists = HashMap::<i32, Vec<i32>>::new();
+    for (i, &before) in order.iter().enumerate() {
+        for &after in order.iter().skip(i + 1) {
+            rule_set.insert(before, after);
+            rule_lists.entry(before).or_default().push(after);
+        }
+    }
+
+    let updates = (0..updates)
+        .map(|_| {
+            let mut pages = order.clone();
+            rng.shuffle(&mut pages);
+            pages.truncate(5 + rng.next() as usize % 19);
+            pages
+        })
+        .collect::<Vec<_>>();
+
+    let mut report = format!("{} rules, {} updates\n", rule_set.len(), updates.len());
+
+    let start = Instant::now();
+    let correct = updates
+        .iter()
+        .filter(|update| is_correctly_ordered_with_lists(update, &rule_lists))
+        .count();
+    report += &format!(
+        "check with lists: {correct} correct in {:?}\n",
+        start.elapsed()
+    );
+
+    let start = Instant::now();
+    let correct = updates
+        .iter()
+        .filter(|update| is_correctly_ordered(update, &rule_set))
+        .count();
+    report += &format!(
+        "check with RuleSet: {correct} correct in {:?}\n",
+        start.elapsed()
+    );
+
+    let start = Instant::now();
+    let sorted_lists = updates
+        .iter()
+        .map(|update| {
+            let mut update = update.clone();
+            update.sort_by(|a, b| {
+                if rule_lists.get(a).is_some_and(|deps| deps.contains(b)) {
+                    Ordering::Less
+                } else if rule_lists.get(b).is_some_and(|deps| deps.contains(a)) {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            });
+            update
+        })
+        .collect::<Vec<_>>();
+    report += &format!("sort with lists: {:?}\n", start.elapsed());
+
+    let start = Instant::now();
+    let sorted_rule_set = updates
+        .iter()
+        .map(|update| {
+            let mut update = update.clone();
+            update.sort_by(|a, b| rule_set.compare(a, b));
+            update
+        })
+        .collect::<Vec<_>>();
+    report += &format!("sort with RuleSet: {:?}\n", start.elapsed());
+
+    if sorted_lists != sorted_rule_set {
+        report += "sorted updates differ\n";
+    }
+
+    report
+}
+
+// The lookup RuleSet replaced, linear search in the list of following pages
+fn is_correctly_ordered_with_lists(update: &[i32], rules: &HashMap<i32, Vec<i32>>) -> bool {
     for (i, page) in update.iter().enumerate() {
         for following_page in update.iter().skip(i + 1) {
-            match rules.get(following_page) {
-                Some(deps) => {
-                    if deps.contains(page) {
-                        return false;
-                    }
+            if let Some(deps) = rules.get(following_page) {
+                if deps.contains(page) {
+                    return false;
                 }
-                None => (),
             }
         }
     }
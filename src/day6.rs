@@ -1,211 +1,814 @@
-use core::{fmt, panic};
-use std::fs::read_to_string;
+use core::fmt;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, read_to_string},
+    io::{self, IsTerminal, Read, Write},
+    path::Path,
+    process::{Command, Stdio},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
 
-pub fn distinct_positions(filename: &str) -> usize {
-    let mut grid = parse_grid(filename);
+use crate::rng::XorShift;
 
-    // Uncomment prints for grid visualization before/after tracing the guards movement
-    // println!("{grid}");
-    trace_guard(&mut grid).ok();
-    // println!("{grid}");
+// Fails if the guard never leaves, as there is no answer then
+pub fn distinct_positions(
+    filename: &str,
+    aliases: &Aliases,
+    rule: MovementRule,
+) -> Result<usize, TraceError> {
+    let mut grid = parse_grid(filename, aliases)?;
 
-    visited_positions(&grid)
+    // See animate for a visualization of the guards movement
+    trace_guard(&mut grid, rule)?;
+
+    Ok(visited_positions(&grid))
+}
+
+// Fails if the guard is already stuck in a loop without an extra obstacle
+pub fn obstacles_causing_a_loop(
+    filename: &str,
+    aliases: &Aliases,
+    rule: MovementRule,
+) -> Result<usize, TraceError> {
+    let mut grid = parse_grid(filename, aliases)?;
+    Ok(possible_obstacles_causing_a_loop(&mut grid, rule)?.len())
+}
+
+// How the guard reacts to an obstacle in front of them. The puzzle's guard
+// (the default) turns right by 90 degrees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MovementRule {
+    pub turn: Turn,
+    // Turn by 45 degrees instead, walking diagonally after an odd number of
+    // turns. Only the position the guard walks to has to be free, not the
+    // two positions next to the diagonal.
+    pub diagonals: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Turn {
+    #[default]
+    Right,
+    Left,
+    // Turn around
+    Reverse,
+    // Right at the first obstacle, left at the second and so on
+    Alternate,
+}
+
+// An extra obstacle that traps the guard in a loop
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopObstacle {
+    pub x: usize,
+    pub y: usize,
+    // The states the guard repeats forever, starting where the loop begins
+    pub cycle: Vec<GuardState>,
+}
+
+// Position and facing direction of the guard before a step
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GuardState {
+    pub x: usize,
+    pub y: usize,
+    pub dir: Direction,
+}
+
+// Every obstacle position causing a loop (in reading order) with the loop it causes
+pub fn loop_obstacles(
+    filename: &str,
+    aliases: &Aliases,
+    rule: MovementRule,
+) -> Result<Vec<LoopObstacle>, TraceError> {
+    let mut grid = parse_grid(filename, aliases)?;
+    let obstacles = possible_obstacles_causing_a_loop(&mut grid.clone(), rule)?;
+
+    Ok(obstacles
+        .into_iter()
+        .filter_map(|(x, y)| {
+            grid.clear_visits();
+            grid.extra = Some((x, y));
+            let cycle = loop_cycle(&mut grid, rule)?;
+            Some(LoopObstacle { x, y, cycle })
+        })
+        .collect())
 }
 
-pub fn obstacles_causing_a_loop(filename: &str) -> usize {
-    let mut grid = parse_grid(filename);
-    possible_obstacles_causing_a_loop(&mut grid).unwrap_or(0)
+// The grid after the guard's walk with an extra obstacle at x, y, drawn like in
+// the puzzle description. Positions that can't take an obstacle (outside the
+// grid, the guard or an existing obstacle) are left as they are.
+pub fn loop_obstacle_grid(
+    filename: &str,
+    aliases: &Aliases,
+    rule: MovementRule,
+    x: usize,
+    y: usize,
+) -> Result<String, TraceError> {
+    let mut grid = parse_grid(filename, aliases)?;
+    let start = Guard::find(&grid, rule)?;
+
+    if x < grid.width && y < grid.height && grid.get(x, y) == Pos::NotVisited {
+        grid.set(x, y, Pos::ExtraObstacle);
+    }
+
+    match trace_guard(&mut grid, rule) {
+        Ok(()) | Err(TraceError::Loop) => (),
+        Err(err) => return Err(err),
+    }
+    grid.set(start.x, start.y, Pos::Guard(start.dir));
+
+    Ok(grid.to_string())
 }
 
-fn possible_obstacles_causing_a_loop(grid: &mut Grid) -> Result<usize, TraceError> {
-    let guard_start = guard_pos(&grid)?;
+// Positions of all extra obstacles which make the guard walk in a loop
+fn possible_obstacles_causing_a_loop(
+    grid: &mut Grid,
+    rule: MovementRule,
+) -> Result<Vec<(usize, usize)>, TraceError> {
+    let guard_start = guard_pos(grid)?;
 
     // Instead of checking every single empty position on the grid, we can
     // just check all the positions where the guard walked without the
     // additional obstacle, limiting the amount of calculations we have to do.
-    let original_grid = grid.clone();
-    trace_guard(grid)?;
+    let mut candidate_grid = grid.clone();
+    trace_guard(grid, rule)?;
 
-    let mut count = 0;
-    for (y, row) in grid.0.iter().enumerate() {
-        for (x, pos) in row.iter().enumerate() {
-            // Not allowed to put an obstacle at the guards starting position
-            if (x, y) == guard_start {
-                continue;
-            }
+    // Each candidate only adds a single obstacle, so it's cheaper to jump
+    // between obstacles with a shared table than to walk the grid. The table
+    // only knows straight lines, diagonal walks need the grid.
+    let mut table = JumpTable::new(&candidate_grid);
+    let guard = Guard::find(&candidate_grid, rule)?;
 
-            if let Pos::Visited(_) = pos {
-                let mut new_grid = original_grid.clone();
-                new_grid.0[y][x] = Pos::ExtraObstacle;
+    let mut obstacles = Vec::new();
+    for (x, y) in grid.visited_positions() {
+        // Not allowed to put an obstacle at the guards starting position
+        if (x, y) == guard_start {
+            continue;
+        }
 
-                if causes_a_loop(&mut new_grid) {
-                    count += 1;
-                }
-            }
+        let causes_a_loop = if rule.diagonals {
+            candidate_grid.clear_visits();
+            candidate_grid.extra = Some((x, y));
+            matches!(
+                trace_from(&mut candidate_grid, guard.clone()),
+                Err(TraceError::Loop)
+            )
+        } else {
+            table.extra = Some((x, y));
+            table.causes_a_loop(&guard)
+        };
+
+        if causes_a_loop {
+            obstacles.push((x, y));
         }
     }
 
-    Ok(count)
+    Ok(obstacles)
 }
 
-fn causes_a_loop(grid: &mut Grid) -> bool {
-    match trace_guard(grid) {
-        Err(TraceError::Loop) => true,
-        _ => false,
+// Walk like trace_guard, but remember every state to return the repeated part
+// of the walk. None if the guard leaves the grid.
+fn loop_cycle(grid: &mut Grid, rule: MovementRule) -> Option<Vec<GuardState>> {
+    let mut guard = Guard::find(grid, rule).ok()?;
+    // With the visit bit, as an alternating guard only repeats itself once
+    // the next turn goes the same way as well
+    let mut states = Vec::new();
+
+    loop {
+        let state = (guard.state(), guard.visit_bit());
+        match guard.step(grid) {
+            Step::Left => return None,
+            Step::Loop => {
+                let start = states.iter().position(|seen| *seen == state)?;
+                return Some(
+                    states
+                        .split_off(start)
+                        .into_iter()
+                        .map(|(state, _)| state)
+                        .collect(),
+                );
+            }
+            Step::Moved | Step::Turned => states.push(state),
+        }
     }
 }
 
-fn parse_grid(filename: &str) -> Grid {
-    Grid(
-        read_to_string(filename)
-            .unwrap_or(String::from(""))
-            .lines()
-            .map(|line| line.chars().map(|c| Pos::from(c)).collect::<Vec<_>>())
-            .collect::<Vec<_>>(),
+fn parse_grid(filename: &str, aliases: &Aliases) -> Result<Grid, ParseError> {
+    parse_text(
+        &read_to_string(filename).unwrap_or(String::from("")),
+        aliases,
     )
 }
 
+// Rows shorter than the longest one are filled up with empty positions,
+// trailing blank lines are ignored
+fn parse_text(text: &str, aliases: &Aliases) -> Result<Grid, ParseError> {
+    let rows = text.lines().enumerate().map(|(y, line)| {
+        line.chars()
+            .enumerate()
+            .map(|(x, c)| {
+                Pos::try_from(aliases.resolve(c)).map_err(|character| ParseError {
+                    row: y + 1,
+                    column: x + 1,
+                    character,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+    });
+    let mut rows = rows.collect::<Result<Vec<_>, _>>()?;
+    while rows.last().is_some_and(Vec::is_empty) {
+        rows.pop();
+    }
+
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let mut grid = Grid::new(width, rows.len());
+    for (y, row) in rows.into_iter().enumerate() {
+        for (x, pos) in row.into_iter().enumerate() {
+            grid.set(x, y, pos);
+        }
+    }
+
+    Ok(grid)
+}
+
+// Extra characters accepted in a map, each standing for one of the usual
+// characters. E.g. '@' for the obstacles in maps drawn by other tools.
+#[derive(Debug, Clone, Default)]
+pub struct Aliases(HashMap<char, char>);
+
+impl Aliases {
+    pub fn new() -> Aliases {
+        Aliases::default()
+    }
+
+    // Read alias as target from now on. Fails with target if that isn't a map
+    // character itself.
+    pub fn insert(&mut self, alias: char, target: char) -> Result<(), char> {
+        Pos::try_from(target)?;
+        self.0.insert(alias, target);
+        Ok(())
+    }
+
+    fn resolve(&self, c: char) -> char {
+        self.0.get(&c).copied().unwrap_or(c)
+    }
+}
+
+// A character that isn't part of the map format, row and column start at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub row: usize,
+    pub column: usize,
+    pub character: char,
+}
+
 fn visited_positions(grid: &Grid) -> usize {
-    grid.0.iter().fold(0, |acc, row| {
-        acc + row.iter().fold(0, |acc_row, pos| {
-            if let Pos::Visited(_) = pos {
-                acc_row + 1
-            } else {
-                acc_row
-            }
-        })
-    })
+    grid.visited_positions().len()
 }
 
-fn trace_guard(grid: &mut Grid) -> Result<(), TraceError> {
-    let (mut x, mut y) = guard_pos(grid)?;
-    let mut dir = match &grid.0[y][x] {
-        Pos::Guard(dir) => dir.clone(),
-        _ => return Err(TraceError::GuardNotFound),
-    };
+fn trace_guard(grid: &mut Grid, rule: MovementRule) -> Result<(), TraceError> {
+    let guard = Guard::find(grid, rule)?;
+    trace_from(grid, guard)
+}
 
-    while let Some(new_coords) = next_coords(grid, x, y, &dir) {
-        match grid.0[new_coords.1][new_coords.0] {
-            Pos::Obstacle | Pos::ExtraObstacle => dir = turn_right(&dir),
-            _ => {
-                // There is probably a smarter way to check for a loop. We just assume that a
-                // loop happens if a position is hit more than 4 times (once every direction)
-                if let Pos::Visited(visit_count) = grid.0[y][x] {
-                    if visit_count >= 4 {
-                        return Err(TraceError::Loop);
-                    }
-                }
+// Same as trace_guard, for a guard that was already found
+fn trace_from(grid: &mut Grid, mut guard: Guard) -> Result<(), TraceError> {
+    loop {
+        match guard.step(grid) {
+            Step::Left => return Ok(()),
+            Step::Loop => return Err(TraceError::Loop),
+            Step::Moved | Step::Turned => (),
+        }
+    }
+}
 
-                mark_visited(grid, x, y);
-                (x, y) = new_coords;
-            }
+// The guard walking through the grid, one step at a time
+#[derive(Clone)]
+struct Guard {
+    x: usize,
+    y: usize,
+    dir: Direction,
+    rule: MovementRule,
+    // Number of turns so far, alternating guards turn left after odd ones
+    turns: usize,
+    // Walking off an edge enters the grid again on the opposite side
+    wrap: bool,
+}
+
+#[derive(PartialEq)]
+enum Step {
+    Moved,
+    Turned,
+    // Walked out of the grid
+    Left,
+    Loop,
+}
+
+impl Guard {
+    fn find(grid: &Grid, rule: MovementRule) -> Result<Guard, TraceError> {
+        let (x, y) = guard_pos(grid)?;
+        match grid.get(x, y) {
+            Pos::Guard(dir) => Ok(Guard {
+                x,
+                y,
+                dir,
+                rule,
+                turns: 0,
+                wrap: false,
+            }),
+            _ => Err(TraceError::GuardNotFound),
+        }
+    }
+
+    // All guards in reading order
+    fn find_all(grid: &Grid, rule: MovementRule) -> Vec<Guard> {
+        grid.positions()
+            .filter_map(|(x, y, pos)| match pos {
+                Pos::Guard(dir) => Some(Guard {
+                    x,
+                    y,
+                    dir,
+                    rule,
+                    turns: 0,
+                    wrap: false,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn turn(&mut self) {
+        // In eighths of a full turn, clockwise
+        let quarter = if self.rule.diagonals { 1 } else { 2 };
+        let eighths = match self.rule.turn {
+            Turn::Right => quarter,
+            Turn::Left => 8 - quarter,
+            Turn::Reverse => 4,
+            Turn::Alternate if self.turns.is_multiple_of(2) => quarter,
+            Turn::Alternate => 8 - quarter,
+        };
+
+        self.dir = self.dir.clockwise(eighths);
+        self.turns += 1;
+    }
+
+    // Flag for Pos::Visited. An alternating guard is only in the same state
+    // again if the next turn goes the same way, so it uses separate flags for
+    // both cases.
+    fn visit_bit(&self) -> u16 {
+        match self.rule.turn {
+            Turn::Alternate if self.turns % 2 == 1 => self.dir.bit() << 8,
+            _ => self.dir.bit(),
         }
     }
 
-    mark_visited(grid, x, y);
-    Ok(())
+    fn state(&self) -> GuardState {
+        GuardState {
+            x: self.x,
+            y: self.y,
+            dir: self.dir.clone(),
+        }
+    }
+
+    // Turn or move by one position, marking the position with the direction the
+    // guard faced there. Once the guard left the grid or is stuck in a loop,
+    // don't call step anymore.
+    fn step(&mut self, grid: &mut Grid) -> Step {
+        let new_coords = if self.wrap {
+            Some(wrapped_coords(grid, self.x, self.y, &self.dir))
+        } else {
+            next_coords(grid, self.x, self.y, &self.dir)
+        };
+        let Some(new_coords) = new_coords else {
+            grid.mark_visited(self.x, self.y, self.visit_bit());
+            return Step::Left;
+        };
+
+        // Been here facing the same way (and about to turn the same way)
+        // before, so from here on the guard repeats the same path forever
+        if grid.visits(self.x, self.y) & self.visit_bit() != 0 {
+            return Step::Loop;
+        }
+        grid.mark_visited(self.x, self.y, self.visit_bit());
+
+        if grid.is_obstacle(new_coords.0, new_coords.1) {
+            self.turn();
+            Step::Turned
+        } else {
+            (self.x, self.y) = new_coords;
+            Step::Moved
+        }
+    }
 }
 
 fn guard_pos(grid: &Grid) -> Result<(usize, usize), TraceError> {
-    let y = grid
-        .0
+    // Only look at the positions that can be a guard instead of building
+    // every Pos
+    let mut from = 0;
+    while let Some(i) = grid.tiles[from..]
         .iter()
-        .position(|row| row.iter().any(|pos| is_guard(pos)))
-        .ok_or(TraceError::GuardNotFound)?;
+        .position(|&tile| tile >= TILE_GUARD)
+    {
+        let (x, y) = ((from + i) % grid.width, (from + i) / grid.width);
+        if is_guard(&grid.get(x, y)) {
+            return Ok((x, y));
+        }
+        from += i + 1;
+    }
 
-    let x = grid.0[y]
-        .iter()
-        .position(|pos| is_guard(pos))
-        .ok_or(TraceError::GuardNotFound)?;
+    Err(TraceError::GuardNotFound)
+}
 
-    Ok((x, y))
+// Return the next coordinates after moving in direction or None if leaving the area
+fn next_coords(grid: &Grid, x: usize, y: usize, dir: &Direction) -> Option<(usize, usize)> {
+    let (dx, dy) = dir.delta();
+    let new_x = x.checked_add_signed(dx).filter(|&x| x < grid.width)?;
+    let new_y = y.checked_add_signed(dy).filter(|&y| y < grid.height)?;
+    Some((new_x, new_y))
 }
 
-fn mark_visited(grid: &mut Grid, x: usize, y: usize) {
-    if let Pos::Visited(visit_count) = grid.0[y][x] {
-        grid.0[y][x] = Pos::Visited(visit_count + 1);
-    } else {
-        grid.0[y][x] = Pos::Visited(1);
-    }
+// The next coordinates after moving in direction, coming back in on the
+// opposite side when leaving the area
+fn wrapped_coords(grid: &Grid, x: usize, y: usize, dir: &Direction) -> (usize, usize) {
+    let (dx, dy) = dir.delta();
+    (
+        (x + grid.width).wrapping_add_signed(dx) % grid.width,
+        (y + grid.height).wrapping_add_signed(dy) % grid.height,
+    )
 }
 
-fn turn_right(dir: &Direction) -> Direction {
-    match dir {
-        Direction::Up => Direction::Right,
-        Direction::Right => Direction::Down,
-        Direction::Down => Direction::Left,
-        Direction::Left => Direction::Up,
-    }
+// Obstacle positions sorted by row and column, to find the next obstacle in
+// any direction with a binary search instead of walking cell by cell. One
+// extra obstacle can be added on top without rebuilding the table.
+struct JumpTable {
+    width: usize,
+    height: usize,
+    // x of the obstacles in each row and y of the obstacles in each column
+    rows: Vec<Vec<usize>>,
+    columns: Vec<Vec<usize>>,
+    extra: Option<(usize, usize)>,
 }
 
-// Return the next coordinates after moving in direction or None if leaving the area
-fn next_coords(grid: &mut Grid, x: usize, y: usize, dir: &Direction) -> Option<(usize, usize)> {
-    let len_x = grid.0[0].len();
-    let len_y = grid.0.len();
+// Where a straight walk ends
+#[derive(Debug, PartialEq)]
+enum Jump {
+    // In front of an obstacle
+    Blocked(usize, usize),
+    // On the last position before leaving the grid
+    Exit(usize, usize),
+}
+
+impl JumpTable {
+    fn new(grid: &Grid) -> JumpTable {
+        let mut rows = vec![Vec::new(); grid.height];
+        let mut columns = vec![Vec::new(); grid.width];
+
+        // Filled in order, so the lists are already sorted
+        for (y, row) in grid.tiles.chunks(grid.width.max(1)).enumerate() {
+            for (x, &tile) in row.iter().enumerate() {
+                if tile == TILE_OBSTACLE
+                    || tile == TILE_EXTRA_OBSTACLE
+                    || grid.extra == Some((x, y))
+                {
+                    rows[y].push(x);
+                    columns[x].push(y);
+                }
+            }
+        }
+
+        JumpTable {
+            width: grid.width,
+            height: grid.height,
+            rows,
+            columns,
+            extra: None,
+        }
+    }
+
+    // Walk straight from x, y until reaching an obstacle or the edge. Only
+    // works for the four main directions.
+    fn jump(&self, x: usize, y: usize, dir: &Direction) -> Jump {
+        // Coordinate along the walk, the obstacles on the line and the extra
+        // obstacle if it's on the same line
+        let (from, line, extra) = match dir {
+            Direction::Up | Direction::Down => (
+                y,
+                &self.columns[x],
+                self.extra.filter(|extra| extra.0 == x).map(|extra| extra.1),
+            ),
+            Direction::Left | Direction::Right => (
+                x,
+                &self.rows[y],
+                self.extra.filter(|extra| extra.1 == y).map(|extra| extra.0),
+            ),
+            _ => unreachable!("no jumps along diagonals"),
+        };
+
+        let forward = matches!(dir, Direction::Down | Direction::Right);
+        let obstacle = if forward {
+            let found = line.get(line.partition_point(|&pos| pos <= from)).copied();
+            let extra = extra.filter(|&extra| extra > from);
+            found.into_iter().chain(extra).min()
+        } else {
+            // Skip an obstacle on the start position itself
+            let before = line.partition_point(|&pos| pos < from);
+            let found = before.checked_sub(1).map(|i| line[i]);
+            let extra = extra.filter(|&extra| extra < from);
+            found.into_iter().chain(extra).max()
+        };
 
-    match dir {
-        Direction::Up => (y > 0).then(|| (x, y - 1)),
-        Direction::Down => (y < len_y - 1).then(|| (x, y + 1)),
-        Direction::Left => (x > 0).then(|| (x - 1, y)),
-        Direction::Right => (x < len_x - 1).then(|| (x + 1, y)),
+        let end = match (obstacle, forward) {
+            (Some(obstacle), true) => obstacle - 1,
+            (Some(obstacle), false) => obstacle + 1,
+            (None, true) => match dir {
+                Direction::Down => self.height - 1,
+                _ => self.width - 1,
+            },
+            (None, false) => 0,
+        };
+
+        let (x, y) = match dir {
+            Direction::Up | Direction::Down => (x, end),
+            _ => (end, y),
+        };
+        match obstacle {
+            Some(_) => Jump::Blocked(x, y),
+            None => Jump::Exit(x, y),
+        }
+    }
+
+    // Walk segment by segment. Only the positions where the guard turns need
+    // to be remembered, running into the same obstacle from the same
+    // direction twice means a loop.
+    fn causes_a_loop(&self, guard: &Guard) -> bool {
+        let mut turns = HashSet::new();
+        let mut guard = guard.clone();
+
+        loop {
+            match self.jump(guard.x, guard.y, &guard.dir) {
+                Jump::Exit(..) => return false,
+                Jump::Blocked(x, y) => {
+                    if !turns.insert((x, y, guard.visit_bit())) {
+                        return true;
+                    }
+                    (guard.x, guard.y) = (x, y);
+                    guard.turn();
+                }
+            }
+        }
     }
 }
 
 fn is_guard(pos: &Pos) -> bool {
-    match pos {
-        Pos::Guard(_) => true,
-        _ => false,
-    }
+    matches!(pos, Pos::Guard(_))
 }
 
-enum TraceError {
+#[derive(Debug)]
+pub enum TraceError {
+    Parse(ParseError),
     GuardNotFound,
     Loop,
 }
 
-#[derive(PartialEq, Clone)]
-enum Direction {
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Direction {
     Up,
     Down,
     Left,
     Right,
+    // Only used when walking with diagonals
+    UpRight,
+    DownRight,
+    DownLeft,
+    UpLeft,
+}
+
+// Clockwise, starting up
+const DIRECTIONS: [Direction; 8] = [
+    Direction::Up,
+    Direction::UpRight,
+    Direction::Right,
+    Direction::DownRight,
+    Direction::Down,
+    Direction::DownLeft,
+    Direction::Left,
+    Direction::UpLeft,
+];
+
+impl Direction {
+    // Flag for the set of directions in Pos::Visited
+    fn bit(&self) -> u16 {
+        1 << self.index()
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Direction::Up => 0,
+            Direction::UpRight => 1,
+            Direction::Right => 2,
+            Direction::DownRight => 3,
+            Direction::Down => 4,
+            Direction::DownLeft => 5,
+            Direction::Left => 6,
+            Direction::UpLeft => 7,
+        }
+    }
+
+    // Turned clockwise by eighths of a full turn
+    fn clockwise(&self, eighths: usize) -> Direction {
+        DIRECTIONS[(self.index() + eighths) % 8].clone()
+    }
+
+    fn delta(&self) -> (isize, isize) {
+        match self {
+            Direction::Up => (0, -1),
+            Direction::UpRight => (1, -1),
+            Direction::Right => (1, 0),
+            Direction::DownRight => (1, 1),
+            Direction::Down => (0, 1),
+            Direction::DownLeft => (-1, 1),
+            Direction::Left => (-1, 0),
+            Direction::UpLeft => (-1, -1),
+        }
+    }
 }
 
 #[derive(PartialEq, Clone)]
 enum Pos {
     NotVisited,
-    Visited(i32),
+    // Bits of all directions the guard faced at this position, see
+    // Guard::visit_bit
+    Visited(u16),
     Obstacle,
     ExtraObstacle,
     Guard(Direction),
 }
 
+// The map in reading order, with one byte per position for what's there and
+// the directions the guard walked in (see Guard::visit_bit) kept apart. A
+// walk only changes the directions, so the same map can be walked again after
+// clearing them.
 #[derive(Clone)]
-struct Grid(Vec<Vec<Pos>>);
+struct Grid {
+    width: usize,
+    height: usize,
+    tiles: Vec<u8>,
+    visits: Vec<u16>,
+    // Indices of the positions with visits, so clearing them takes as long as
+    // the walk and not as long as the map is big
+    visited: Vec<usize>,
+    // Obstacle laid over the map, to try out obstacles without changing tiles
+    extra: Option<(usize, usize)>,
+}
+
+const TILE_EMPTY: u8 = 0;
+// Marked as visited in the map read in
+const TILE_VISITED: u8 = 1;
+const TILE_OBSTACLE: u8 = 2;
+const TILE_EXTRA_OBSTACLE: u8 = 3;
+// Plus the direction's index
+const TILE_GUARD: u8 = 4;
+
+impl Grid {
+    fn new(width: usize, height: usize) -> Grid {
+        Grid {
+            width,
+            height,
+            tiles: vec![TILE_EMPTY; width * height],
+            visits: vec![0; width * height],
+            visited: Vec::new(),
+            extra: None,
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> Pos {
+        let i = y * self.width + x;
+        match self.tiles[i] {
+            _ if self.extra == Some((x, y)) => Pos::ExtraObstacle,
+            TILE_OBSTACLE => Pos::Obstacle,
+            TILE_EXTRA_OBSTACLE => Pos::ExtraObstacle,
+            tile if self.visits[i] != 0 || tile == TILE_VISITED => Pos::Visited(self.visits[i]),
+            tile if tile >= TILE_GUARD => {
+                Pos::Guard(DIRECTIONS[(tile - TILE_GUARD) as usize].clone())
+            }
+            _ => Pos::NotVisited,
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, pos: Pos) {
+        let i = y * self.width + x;
+        self.tiles[i] = match &pos {
+            Pos::NotVisited => TILE_EMPTY,
+            Pos::Visited(_) => TILE_VISITED,
+            Pos::Obstacle => TILE_OBSTACLE,
+            Pos::ExtraObstacle => TILE_EXTRA_OBSTACLE,
+            Pos::Guard(dir) => TILE_GUARD + dir.index() as u8,
+        };
+        if self.visits[i] != 0 {
+            self.visits[i] = 0;
+            self.visited.retain(|&j| j != i);
+        }
+        if let Pos::Visited(dirs) = pos {
+            self.mark_visited(x, y, dirs);
+        }
+    }
+
+    // All positions in reading order
+    fn positions(&self) -> impl Iterator<Item = (usize, usize, Pos)> + '_ {
+        (0..self.height).flat_map(move |y| (0..self.width).map(move |x| (x, y, self.get(x, y))))
+    }
+
+    fn is_obstacle(&self, x: usize, y: usize) -> bool {
+        let tile = self.tiles[y * self.width + x];
+        tile == TILE_OBSTACLE || tile == TILE_EXTRA_OBSTACLE || self.extra == Some((x, y))
+    }
+
+    fn visits(&self, x: usize, y: usize) -> u16 {
+        self.visits[y * self.width + x]
+    }
+
+    fn mark_visited(&mut self, x: usize, y: usize, bit: u16) {
+        let i = y * self.width + x;
+        if self.visits[i] == 0 && bit != 0 {
+            self.visited.push(i);
+        }
+        self.visits[i] |= bit;
+    }
+
+    // Coordinates of the positions that are Pos::Visited in reading order,
+    // without looking at every position
+    fn visited_positions(&self) -> Vec<(usize, usize)> {
+        let marked = self
+            .tiles
+            .iter()
+            .enumerate()
+            .filter(|&(_, &tile)| tile == TILE_VISITED)
+            .map(|(i, _)| i);
+        let walked = self
+            .visited
+            .iter()
+            .copied()
+            .filter(|&i| self.tiles[i] != TILE_VISITED);
+
+        let mut indices = marked.chain(walked).collect::<Vec<_>>();
+        indices.sort_unstable();
+        indices
+            .into_iter()
+            .map(|i| (i % self.width, i / self.width))
+            .collect()
+    }
+
+    // Forget the walk to start another one
+    fn clear_visits(&mut self) {
+        for i in self.visited.drain(..) {
+            self.visits[i] = 0;
+        }
+    }
+}
 
 const CHAR_UP: char = '^';
 const CHAR_DOWN: char = 'v';
 const CHAR_LEFT: char = '<';
 const CHAR_RIGHT: char = '>';
+// Diagonal guards are never read from a map, only drawn
+const CHAR_UP_RIGHT: char = '↗';
+const CHAR_DOWN_RIGHT: char = '↘';
+const CHAR_DOWN_LEFT: char = '↙';
+const CHAR_UP_LEFT: char = '↖';
 
 const CHAR_NOT_VISITED: char = '.';
 const CHAR_VISITED: char = 'X';
+const CHAR_VISITED_VERTICAL: char = '|';
+const CHAR_VISITED_HORIZONTAL: char = '-';
+const CHAR_VISITED_RISING: char = '/';
+const CHAR_VISITED_FALLING: char = '\\';
+const CHAR_VISITED_BOTH: char = '+';
 const CHAR_OBSTACLE: char = '#';
 const CHAR_EXTRA_OBSTACLE: char = 'O';
 
-impl From<char> for Pos {
-    fn from(value: char) -> Pos {
-        match value {
+impl TryFrom<char> for Pos {
+    // The character that isn't part of a map
+    type Error = char;
+
+    fn try_from(value: char) -> Result<Pos, char> {
+        Ok(match value {
             CHAR_NOT_VISITED => Pos::NotVisited,
-            CHAR_VISITED => Pos::Visited(1),
+            // Directions aren't known when reading a grid back in
+            CHAR_VISITED
+            | CHAR_VISITED_VERTICAL
+            | CHAR_VISITED_HORIZONTAL
+            | CHAR_VISITED_RISING
+            | CHAR_VISITED_FALLING
+            | CHAR_VISITED_BOTH => Pos::Visited(0),
             CHAR_OBSTACLE => Pos::Obstacle,
             CHAR_EXTRA_OBSTACLE => Pos::ExtraObstacle,
             CHAR_UP => Pos::Guard(Direction::Up),
             CHAR_DOWN => Pos::Guard(Direction::Down),
             CHAR_LEFT => Pos::Guard(Direction::Left),
             CHAR_RIGHT => Pos::Guard(Direction::Right),
-            _ => panic!("Can't convert from '{value}' to Pos"),
-        }
+            _ => return Err(value),
+        })
     }
 }
 
@@ -221,16 +824,51 @@ impl fmt::Display for Direction {
                 Direction::Down => CHAR_DOWN,
                 Direction::Left => CHAR_LEFT,
                 Direction::Right => CHAR_RIGHT,
+                Direction::UpRight => CHAR_UP_RIGHT,
+                Direction::DownRight => CHAR_DOWN_RIGHT,
+                Direction::DownLeft => CHAR_DOWN_LEFT,
+                Direction::UpLeft => CHAR_UP_LEFT,
             }
         )
     }
 }
 
+impl fmt::Display for GuardState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}, {}) {}", self.x, self.y, self.dir)
+    }
+}
+
 impl fmt::Display for Pos {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Pos::NotVisited => write!(f, "{CHAR_NOT_VISITED}"),
-            Pos::Visited(_) => write!(f, "{CHAR_VISITED}"),
+            // Path drawn like in the puzzle description, with a + wherever
+            // the guard crossed in more than one line
+            Pos::Visited(dirs) => {
+                // Both turns of an alternating guard look the same
+                let dirs = (dirs | dirs >> 8) & 0xff;
+                let lines = [
+                    (Direction::Up, Direction::Down, CHAR_VISITED_VERTICAL),
+                    (Direction::Left, Direction::Right, CHAR_VISITED_HORIZONTAL),
+                    (Direction::UpRight, Direction::DownLeft, CHAR_VISITED_RISING),
+                    (
+                        Direction::UpLeft,
+                        Direction::DownRight,
+                        CHAR_VISITED_FALLING,
+                    ),
+                ];
+
+                let mut crossed = lines
+                    .iter()
+                    .filter(|(a, b, _)| dirs & (a.bit() | b.bit()) != 0)
+                    .map(|(_, _, c)| c);
+                match (crossed.next(), crossed.next()) {
+                    (Some(c), None) => write!(f, "{c}"),
+                    (Some(_), Some(_)) => write!(f, "{CHAR_VISITED_BOTH}"),
+                    (None, _) => write!(f, "{CHAR_VISITED}"),
+                }
+            }
             Pos::Obstacle => write!(f, "{CHAR_OBSTACLE}"),
             Pos::ExtraObstacle => write!(f, "{CHAR_EXTRA_OBSTACLE}"),
             Pos::Guard(dir) => write!(f, "{dir}"),
@@ -238,15 +876,1131 @@ impl fmt::Display for Pos {
     }
 }
 
+impl Grid {
+    // Memory used by the positions, for comparing representations
+    fn heap_bytes(&self) -> usize {
+        self.tiles.capacity() * size_of::<u8>()
+            + self.visits.capacity() * size_of::<u16>()
+            + self.visited.capacity() * size_of::<usize>()
+    }
+}
+
 impl fmt::Display for Grid {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for row in self.0.iter() {
-            for pos in row {
-                write!(f, "{pos}")?
+        for y in 0..self.height {
+            for x in 0..self.width {
+                write!(f, "{}", self.get(x, y))?
+            }
+            writeln!(f)?
+        }
+
+        Ok(())
+    }
+}
+
+impl From<ParseError> for TraceError {
+    fn from(err: ParseError) -> TraceError {
+        TraceError::Parse(err)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown character {:?} in row {}, column {}",
+            self.character, self.row, self.column
+        )
+    }
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::Parse(err) => write!(f, "{err}"),
+            TraceError::GuardNotFound => write!(f, "no guard in the grid"),
+            TraceError::Loop => write!(f, "the guard is stuck in a loop"),
+        }
+    }
+}
+
+// Wrap-around maps, where the guard can't leave and always ends up in a loop
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrappedLoop {
+    // Steps (moves and turns) before the guard enters the loop
+    pub entry_step: usize,
+    // Steps for one round of the loop
+    pub length: usize,
+}
+
+pub fn wrapped_loop(
+    filename: &str,
+    aliases: &Aliases,
+    rule: MovementRule,
+) -> Result<WrappedLoop, TraceError> {
+    let mut grid = parse_grid(filename, aliases)?;
+    trace_wrapped(&mut grid, rule)
+}
+
+// Number of positions where an extra obstacle gives a loop of a different
+// length on a wrap-around map
+pub fn obstacles_changing_the_loop(
+    filename: &str,
+    aliases: &Aliases,
+    rule: MovementRule,
+) -> Result<usize, TraceError> {
+    changing_the_loop(&parse_grid(filename, aliases)?, rule)
+}
+
+fn changing_the_loop(original_grid: &Grid, rule: MovementRule) -> Result<usize, TraceError> {
+    let guard_start = guard_pos(original_grid)?;
+
+    // Like for obstacles_causing_a_loop, an obstacle somewhere the guard
+    // never walks can't change anything
+    let mut grid = original_grid.clone();
+    let original_loop = trace_wrapped(&mut grid, rule)?;
+
+    let mut guard = Guard::find(original_grid, rule)?;
+    guard.wrap = true;
+    let mut candidate_grid = original_grid.clone();
+    let mut count = 0;
+    for (x, y) in grid.visited_positions() {
+        if (x, y) == guard_start {
+            continue;
+        }
+
+        candidate_grid.clear_visits();
+        candidate_grid.extra = Some((x, y));
+        if trace_wrapped_from(&mut candidate_grid, guard.clone()).length != original_loop.length {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+fn trace_wrapped(grid: &mut Grid, rule: MovementRule) -> Result<WrappedLoop, TraceError> {
+    let mut guard = Guard::find(grid, rule)?;
+    guard.wrap = true;
+    Ok(trace_wrapped_from(grid, guard))
+}
+
+fn trace_wrapped_from(grid: &mut Grid, mut guard: Guard) -> WrappedLoop {
+    // Every state so far, to find where the loop started once step notices
+    // a repeated state
+    let mut states = Vec::new();
+
+    loop {
+        let state = (guard.x, guard.y, guard.visit_bit());
+        match guard.step(grid) {
+            Step::Loop => {
+                let entry_step = states.iter().position(|seen| *seen == state).unwrap_or(0);
+                return WrappedLoop {
+                    entry_step,
+                    length: states.len() - entry_step,
+                };
+            }
+            Step::Left => unreachable!("the guard can't leave a wrap-around map"),
+            Step::Moved | Step::Turned => states.push(state),
+        }
+    }
+}
+
+// Extra obstacles placed to make the guard's walk as long or as short as
+// possible
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Goal {
+    // Most distinct positions visited before leaving
+    Maximise,
+    // Fewest distinct positions visited before leaving
+    Minimise,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlacementOptions {
+    pub goal: Goal,
+    // Number of extra obstacles to place
+    pub obstacles: usize,
+    // Placements kept after each obstacle and reported at the end. The first
+    // obstacle is tried everywhere, the next ones only next to the kept
+    // placements, so a width of 1 is a greedy search.
+    pub beam_width: usize,
+}
+
+impl Default for PlacementOptions {
+    fn default() -> PlacementOptions {
+        PlacementOptions {
+            goal: Goal::Maximise,
+            obstacles: 1,
+            beam_width: 5,
+        }
+    }
+}
+
+// Extra obstacles in reading order and the distinct positions the guard visits
+// with them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    pub obstacles: Vec<(usize, usize)>,
+    pub coverage: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlacementReport {
+    // Distinct positions visited without extra obstacles
+    pub original: usize,
+    // Best placement first
+    pub placements: Vec<Placement>,
+}
+
+pub fn optimise_placement(
+    filename: &str,
+    aliases: &Aliases,
+    rule: MovementRule,
+    options: &PlacementOptions,
+) -> Result<PlacementReport, TraceError> {
+    best_placements(&parse_grid(filename, aliases)?, rule, options)
+}
+
+fn best_placements(
+    original_grid: &Grid,
+    rule: MovementRule,
+    options: &PlacementOptions,
+) -> Result<PlacementReport, TraceError> {
+    let guard = Guard::find(original_grid, rule)?;
+    let mut grid = original_grid.clone();
+    trace_from(&mut grid, guard.clone())?;
+    let original = visited_positions(&grid);
+
+    let mut beam = vec![Placement {
+        obstacles: Vec::new(),
+        coverage: original,
+    }];
+    for _ in 0..options.obstacles {
+        // Different orders of placing the same obstacles end up the same
+        let mut seen = HashSet::new();
+        let mut next = Vec::new();
+
+        for placement in &beam {
+            let mut candidate_grid = original_grid.clone();
+            for &(x, y) in &placement.obstacles {
+                candidate_grid.set(x, y, Pos::ExtraObstacle);
+            }
+
+            // Like for obstacles_causing_a_loop, another obstacle only changes
+            // the walk if it's somewhere the guard walks. Anywhere else it
+            // keeps the coverage, so the first such position stands for all
+            // of them.
+            trace_from(&mut candidate_grid, guard.clone())?;
+            let off_walk = candidate_grid
+                .positions()
+                .find(|(_, _, pos)| *pos == Pos::NotVisited)
+                .map(|(x, y, _)| (x, y));
+            if let Some(obstacles) = off_walk.map(|extra| with_obstacle(placement, extra)) {
+                if seen.insert(obstacles.clone()) {
+                    next.push(Placement {
+                        obstacles,
+                        coverage: placement.coverage,
+                    });
+                }
+            }
+
+            for (x, y) in candidate_grid.visited_positions() {
+                if (x, y) == (guard.x, guard.y) {
+                    continue;
+                }
+
+                let obstacles = with_obstacle(placement, (x, y));
+                if !seen.insert(obstacles.clone()) {
+                    continue;
+                }
+
+                // A guard stuck in a loop never leaves, so there's nothing to
+                // compare
+                candidate_grid.clear_visits();
+                candidate_grid.extra = Some((x, y));
+                if trace_from(&mut candidate_grid, guard.clone()).is_ok() {
+                    next.push(Placement {
+                        obstacles,
+                        coverage: visited_positions(&candidate_grid),
+                    });
+                }
+            }
+        }
+
+        next.sort_by(|a, b| {
+            let coverage = match options.goal {
+                Goal::Maximise => b.coverage.cmp(&a.coverage),
+                Goal::Minimise => a.coverage.cmp(&b.coverage),
+            };
+            let reading_order = |placement: &Placement| {
+                placement
+                    .obstacles
+                    .iter()
+                    .map(|&(x, y)| (y, x))
+                    .collect::<Vec<_>>()
+            };
+            coverage.then_with(|| reading_order(a).cmp(&reading_order(b)))
+        });
+        next.truncate(options.beam_width.max(1));
+        beam = next;
+    }
+
+    Ok(PlacementReport {
+        original,
+        placements: beam,
+    })
+}
+
+// The placement's obstacles plus another one, in reading order
+fn with_obstacle(placement: &Placement, extra: (usize, usize)) -> Vec<(usize, usize)> {
+    let mut obstacles = placement.obstacles.clone();
+    obstacles.push(extra);
+    obstacles.sort_by_key(|&(x, y)| (y, x));
+    obstacles
+}
+
+// Several guards walking at the same time
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    // Guards walk through each other
+    Ignore,
+    // Guards stop for good where they meet
+    Stop,
+    // Guards turn at other guards like at an obstacle
+    Obstacle,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatrolSummary {
+    // How the walk of each guard ended, with guards in reading order of their
+    // start positions
+    pub outcomes: Vec<Outcome>,
+    // Rounds until every guard left, stopped or got stuck in a loop
+    pub rounds: usize,
+    // Positions visited by any of the guards
+    pub distinct_positions: usize,
+}
+
+// Walk all guards of the grid in lockstep. Every round each guard does one
+// step, in reading order of their start positions, so a guard sees where the
+// guards before it already moved to this round.
+pub fn patrol(
+    filename: &str,
+    aliases: &Aliases,
+    rule: MovementRule,
+    policy: CollisionPolicy,
+) -> Result<PatrolSummary, TraceError> {
+    let mut grid = parse_grid(filename, aliases)?;
+    let (summary, _) = patrol_grid(&mut grid, rule, policy)?;
+    Ok(summary)
+}
+
+// Same as patrol, but returns the grid with the paths of all guards drawn
+pub fn patrol_map(
+    filename: &str,
+    aliases: &Aliases,
+    rule: MovementRule,
+    policy: CollisionPolicy,
+) -> Result<(PatrolSummary, String), TraceError> {
+    let mut grid = parse_grid(filename, aliases)?;
+    let (summary, guards) = patrol_grid(&mut grid, rule, policy)?;
+
+    for guard in guards {
+        if !matches!(grid.get(guard.x, guard.y), Pos::Visited(_)) {
+            grid.set(guard.x, guard.y, Pos::Guard(guard.dir));
+        }
+    }
+    Ok((summary, grid.to_string()))
+}
+
+// Returns the summary and the guards where they started
+fn patrol_grid(
+    grid: &mut Grid,
+    rule: MovementRule,
+    policy: CollisionPolicy,
+) -> Result<(PatrolSummary, Vec<Guard>), TraceError> {
+    let starts = Guard::find_all(grid, rule);
+    if starts.is_empty() {
+        return Err(TraceError::GuardNotFound);
+    }
+
+    let mut guards = starts.clone();
+    // None while the guard is still walking
+    let mut outcomes = vec![None; guards.len()];
+    // With collisions ignored the guards don't affect each other, so every
+    // guard loops on its own. Otherwise only a repeated state of all guards
+    // together is a loop. States use the visit bit instead of the direction,
+    // see Guard::visit_bit.
+    let mut seen = vec![HashSet::new(); guards.len()];
+    let mut seen_rounds = HashSet::new();
+    let mut rounds = 0;
+
+    loop {
+        if policy == CollisionPolicy::Ignore {
+            for (i, guard) in guards.iter().enumerate() {
+                if outcomes[i].is_none() && !seen[i].insert(patrol_state(guard)) {
+                    outcomes[i] = Some(Outcome::Loop);
+                }
+            }
+        } else {
+            let round = guards
+                .iter()
+                .zip(&outcomes)
+                .map(|(guard, outcome)| outcome.is_none().then(|| patrol_state(guard)))
+                .collect::<Vec<_>>();
+            if !seen_rounds.insert(round) {
+                for outcome in outcomes.iter_mut().filter(|outcome| outcome.is_none()) {
+                    *outcome = Some(Outcome::Loop);
+                }
             }
-            writeln!(f, "")?
         }
 
+        if outcomes.iter().all(Option::is_some) {
+            break;
+        }
+
+        rounds += 1;
+        for i in 0..guards.len() {
+            if outcomes[i].is_some() {
+                continue;
+            }
+
+            // Guards that left the grid are out of the way
+            let others = (0..guards.len())
+                .filter(|&j| j != i && outcomes[j] != Some(Outcome::Left))
+                .collect::<Vec<_>>();
+            let occupied = |(x, y): (usize, usize)| {
+                others.iter().any(|&j| (guards[j].x, guards[j].y) == (x, y))
+            };
+
+            let guard = &guards[i];
+            grid.mark_visited(guard.x, guard.y, guard.visit_bit());
+            let Some(new_coords) = next_coords(grid, guard.x, guard.y, &guard.dir) else {
+                outcomes[i] = Some(Outcome::Left);
+                continue;
+            };
+
+            let blocked = grid.is_obstacle(new_coords.0, new_coords.1)
+                || (policy == CollisionPolicy::Obstacle && occupied(new_coords));
+
+            if blocked {
+                guards[i].turn();
+            } else {
+                if policy == CollisionPolicy::Stop && occupied(new_coords) {
+                    outcomes[i] = Some(Outcome::Collided);
+                    for &j in &others {
+                        if (guards[j].x, guards[j].y) == new_coords && outcomes[j].is_none() {
+                            outcomes[j] = Some(Outcome::Collided);
+                        }
+                    }
+                }
+                (guards[i].x, guards[i].y) = new_coords;
+            }
+        }
+    }
+
+    // Guards that stopped didn't get to mark their last position
+    for (guard, outcome) in guards.iter().zip(&outcomes) {
+        if *outcome == Some(Outcome::Collided) {
+            grid.mark_visited(guard.x, guard.y, guard.visit_bit());
+        }
+    }
+
+    let summary = PatrolSummary {
+        outcomes: outcomes.into_iter().flatten().collect(),
+        rounds,
+        distinct_positions: visited_positions(grid),
+    };
+    Ok((summary, starts))
+}
+
+fn patrol_state(guard: &Guard) -> (usize, usize, u16) {
+    (guard.x, guard.y, guard.visit_bit())
+}
+
+// Step by step animation of the guard's walk
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalkSummary {
+    // Number of moves and turns
+    pub steps: usize,
+    pub turns: usize,
+    pub distinct_positions: usize,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    // The guard walked out of the grid
+    Left,
+    Loop,
+    // The animation was quit before the walk ended
+    Stopped,
+    // Ran into another guard and stopped there
+    Collided,
+}
+
+#[derive(Debug)]
+pub enum AnimationError {
+    Trace(TraceError),
+    Io(io::Error),
+}
+
+const ANSI_CLEAR: &str = "\x1b[2J";
+const ANSI_HOME: &str = "\x1b[H";
+const ANSI_HIDE_CURSOR: &str = "\x1b[?25l";
+const ANSI_SHOW_CURSOR: &str = "\x1b[?25h";
+
+// Draw every step of the walk in the terminal, waiting delay between steps.
+// Space pauses and resumes, s advances a single step (and pauses), q quits.
+pub fn animate(
+    filename: &str,
+    aliases: &Aliases,
+    rule: MovementRule,
+    delay: Duration,
+) -> Result<WalkSummary, AnimationError> {
+    let mut grid = parse_grid(filename, aliases)?;
+    let keys = key_presses();
+    let _raw = RawTerminal::enable();
+
+    let mut out = io::stdout().lock();
+    write!(out, "{ANSI_CLEAR}{ANSI_HIDE_CURSOR}")?;
+
+    let mut paused = false;
+    let summary = walk(&mut grid, rule, |step, grid, guard| {
+        writeln!(
+            out,
+            "{ANSI_HOME}{}step {step} (space: pause, s: single step, q: quit)",
+            frame(grid, guard)
+        )?;
+        out.flush()?;
+
+        loop {
+            let key = if paused {
+                keys.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                keys.recv_timeout(delay)
+            };
+
+            match key {
+                Ok(b' ') => paused = !paused,
+                Ok(b's') => {
+                    paused = true;
+                    return Ok(true);
+                }
+                Ok(b'q') => return Ok(false),
+                Ok(_) => (),
+                Err(RecvTimeoutError::Timeout) => return Ok(true),
+                // No keyboard input (stdin is not a terminal), just keep going
+                Err(RecvTimeoutError::Disconnected) => {
+                    thread::sleep(delay);
+                    return Ok(true);
+                }
+            }
+
+            if !paused {
+                return Ok(true);
+            }
+        }
+    });
+
+    write!(out, "{ANSI_SHOW_CURSOR}")?;
+    summary
+}
+
+// Headless animation, writes every step as a text file into dir
+pub fn write_frames(
+    filename: &str,
+    aliases: &Aliases,
+    rule: MovementRule,
+    dir: &Path,
+) -> Result<WalkSummary, AnimationError> {
+    let mut grid = parse_grid(filename, aliases)?;
+    fs::create_dir_all(dir)?;
+
+    walk(&mut grid, rule, |step, grid, guard| {
+        fs::write(
+            dir.join(format!("frame_{step:05}.txt")),
+            frame(grid, guard).to_string(),
+        )?;
+        Ok(true)
+    })
+}
+
+// Walk until the guard leaves or loops, calling on_step before the first and
+// after every step. Stops early if on_step returns false.
+fn walk(
+    grid: &mut Grid,
+    rule: MovementRule,
+    mut on_step: impl FnMut(usize, &Grid, &Guard) -> Result<bool, AnimationError>,
+) -> Result<WalkSummary, AnimationError> {
+    let mut guard = Guard::find(grid, rule)?;
+    let mut summary = WalkSummary {
+        steps: 0,
+        turns: 0,
+        distinct_positions: 0,
+        outcome: Outcome::Stopped,
+    };
+
+    let mut keep_going = on_step(0, grid, &guard)?;
+    while keep_going {
+        let step = guard.step(grid);
+        summary.steps += 1;
+        match step {
+            Step::Turned => summary.turns += 1,
+            Step::Left => summary.outcome = Outcome::Left,
+            Step::Loop => summary.outcome = Outcome::Loop,
+            Step::Moved => (),
+        }
+
+        keep_going = on_step(summary.steps, grid, &guard)? && summary.outcome == Outcome::Stopped;
+    }
+
+    summary.distinct_positions = visited_positions(grid);
+    Ok(summary)
+}
+
+// The grid with the guard drawn at its current position
+fn frame<'a>(grid: &'a Grid, guard: &'a Guard) -> impl fmt::Display + 'a {
+    struct Frame<'a>(&'a Grid, &'a Guard);
+
+    impl fmt::Display for Frame<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let Frame(grid, guard) = self;
+            for y in 0..grid.height {
+                for x in 0..grid.width {
+                    if (x, y) == (guard.x, guard.y) {
+                        write!(f, "{}", guard.dir)?
+                    } else {
+                        write!(f, "{}", grid.get(x, y))?
+                    }
+                }
+                writeln!(f)?
+            }
+
+            Ok(())
+        }
+    }
+
+    Frame(grid, guard)
+}
+
+// Bytes typed on stdin, read on a background thread
+fn key_presses() -> mpsc::Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0; 16];
+        while let Ok(len @ 1..) = io::stdin().read(&mut buf) {
+            if buf[..len].iter().any(|&byte| sender.send(byte).is_err()) {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+// Puts the terminal into unbuffered mode without echo (using stty) while it is
+// alive, so single key presses can be read. Does nothing if stdin is not a
+// terminal.
+struct RawTerminal {
+    saved: Option<String>,
+}
+
+impl RawTerminal {
+    fn enable() -> RawTerminal {
+        if !io::stdin().is_terminal() {
+            return RawTerminal { saved: None };
+        }
+
+        let saved = Command::new("stty")
+            .arg("-g")
+            .stdin(Stdio::inherit())
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+
+        if saved.is_some() {
+            stty(&["-icanon", "-echo", "min", "1"]);
+        }
+
+        RawTerminal { saved }
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.saved {
+            stty(&[saved]);
+        }
+    }
+}
+
+fn stty(args: &[&str]) {
+    Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .status()
+        .ok();
+}
+
+impl From<ParseError> for AnimationError {
+    fn from(err: ParseError) -> AnimationError {
+        AnimationError::Trace(TraceError::Parse(err))
+    }
+}
+
+impl From<TraceError> for AnimationError {
+    fn from(err: TraceError) -> AnimationError {
+        AnimationError::Trace(err)
+    }
+}
+
+impl From<io::Error> for AnimationError {
+    fn from(err: io::Error) -> AnimationError {
+        AnimationError::Io(err)
+    }
+}
+
+impl fmt::Display for AnimationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnimationError::Trace(err) => write!(f, "{err}"),
+            AnimationError::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl fmt::Display for WrappedLoop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "loop of {} steps, entered after {} steps",
+            self.length, self.entry_step
+        )
+    }
+}
+
+impl fmt::Display for PlacementReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "without extra obstacles: {} distinct positions visited",
+            self.original
+        )?;
+        if self.placements.is_empty() {
+            return writeln!(
+                f,
+                "no placement found where the guard still leaves the area"
+            );
+        }
+
+        for (i, placement) in self.placements.iter().enumerate() {
+            let obstacles = placement
+                .obstacles
+                .iter()
+                .map(|(x, y)| format!("({x}, {y})"));
+            writeln!(
+                f,
+                "{i}: obstacles at {}, {} distinct positions visited",
+                obstacles.collect::<Vec<_>>().join(" "),
+                placement.coverage
+            )?;
+        }
         Ok(())
     }
 }
+
+impl fmt::Display for PatrolSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, outcome) in self.outcomes.iter().enumerate() {
+            let outcome = match outcome {
+                Outcome::Left => "left the area",
+                Outcome::Loop => "stuck in a loop",
+                Outcome::Stopped => "stopped",
+                Outcome::Collided => "ran into another guard",
+            };
+            writeln!(f, "guard {i}: {outcome}")?
+        }
+
+        writeln!(
+            f,
+            "{} rounds, {} distinct positions visited by all guards",
+            self.rounds, self.distinct_positions
+        )
+    }
+}
+
+impl fmt::Display for WalkSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} after {} steps ({} turns), {} distinct positions visited",
+            match self.outcome {
+                Outcome::Left => "guard left the area",
+                Outcome::Loop => "guard stuck in a loop",
+                Outcome::Stopped => "stopped",
+                Outcome::Collided => "guard ran into another guard",
+            },
+            self.steps,
+            self.turns,
+            self.distinct_positions
+        )
+    }
+}
+
+// Images of the walk, for reports and slides
+
+// RGB colour of each kind of position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub not_visited: [u8; 3],
+    pub visited: [u8; 3],
+    pub obstacle: [u8; 3],
+    pub extra_obstacle: [u8; 3],
+    pub guard: [u8; 3],
+    // Positions where an extra obstacle would trap the guard in a loop
+    pub candidate: [u8; 3],
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette {
+            not_visited: [0x10, 0x10, 0x20],
+            visited: [0x40, 0x90, 0xff],
+            obstacle: [0xc0, 0xc0, 0xc0],
+            extra_obstacle: [0xff, 0x40, 0x40],
+            guard: [0xff, 0xd0, 0x00],
+            candidate: [0x40, 0xd0, 0x60],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    // Binary PPM (P6), readable by most image tools
+    Ppm,
+    #[cfg(feature = "png")]
+    Png,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageOptions {
+    pub palette: Palette,
+    // Width and height of a position in pixels
+    pub scale: usize,
+    pub format: ImageFormat,
+    // Also mark the positions where an extra obstacle causes a loop
+    pub candidates: bool,
+}
+
+impl Default for ImageOptions {
+    fn default() -> ImageOptions {
+        ImageOptions {
+            palette: Palette::default(),
+            scale: 8,
+            format: ImageFormat::Ppm,
+            candidates: false,
+        }
+    }
+}
+
+impl ImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Ppm => "ppm",
+            #[cfg(feature = "png")]
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+// Write the grid after the guard's walk as an image, with the guard drawn at
+// the start position
+pub fn write_image(
+    filename: &str,
+    aliases: &Aliases,
+    rule: MovementRule,
+    options: &ImageOptions,
+    path: &Path,
+) -> Result<WalkSummary, AnimationError> {
+    let mut grid = parse_grid(filename, aliases)?;
+    let candidates = image_candidates(&grid, rule, options);
+    let start = Guard::find(&grid, rule)?;
+
+    let summary = walk(&mut grid, rule, |_, _, _| Ok(true))?;
+    let image = Image::draw(&grid, &start, &candidates, options);
+    image.save(path, options.format)?;
+
+    Ok(summary)
+}
+
+// Write an image every few steps into dir, plus one of the last step, to be
+// turned into an animation by other tools
+pub fn write_image_frames(
+    filename: &str,
+    aliases: &Aliases,
+    rule: MovementRule,
+    options: &ImageOptions,
+    dir: &Path,
+    every: usize,
+) -> Result<WalkSummary, AnimationError> {
+    let mut grid = parse_grid(filename, aliases)?;
+    let candidates = image_candidates(&grid, rule, options);
+    fs::create_dir_all(dir)?;
+
+    let save = |step: usize, grid: &Grid, guard: &Guard| {
+        let path = dir.join(format!("frame_{step:05}.{}", options.format.extension()));
+        Image::draw(grid, guard, &candidates, options).save(&path, options.format)
+    };
+
+    let mut last = None;
+    let summary = walk(&mut grid, rule, |step, grid, guard| {
+        if step % every.max(1) == 0 {
+            save(step, grid, guard)?;
+        } else {
+            last = Some((step, guard.clone()));
+        }
+        Ok(true)
+    })?;
+
+    if let Some((step, guard)) = last.filter(|(step, _)| *step == summary.steps) {
+        save(step, &grid, &guard)?;
+    }
+
+    Ok(summary)
+}
+
+fn image_candidates(
+    grid: &Grid,
+    rule: MovementRule,
+    options: &ImageOptions,
+) -> Vec<(usize, usize)> {
+    if !options.candidates {
+        return Vec::new();
+    }
+
+    possible_obstacles_causing_a_loop(&mut grid.clone(), rule).unwrap_or_default()
+}
+
+// RGB pixels, row by row
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    fn draw(
+        grid: &Grid,
+        guard: &Guard,
+        candidates: &[(usize, usize)],
+        options: &ImageOptions,
+    ) -> Image {
+        let palette = &options.palette;
+        let scale = options.scale.max(1);
+        let width = grid.width * scale;
+        let height = grid.height * scale;
+        let mut pixels = Vec::with_capacity(width * height * 3);
+
+        for y in 0..grid.height {
+            let colours = (0..grid.width)
+                .map(|x| match grid.get(x, y) {
+                    _ if (x, y) == (guard.x, guard.y) => palette.guard,
+                    Pos::Obstacle => palette.obstacle,
+                    Pos::ExtraObstacle => palette.extra_obstacle,
+                    Pos::Guard(_) => palette.guard,
+                    _ if candidates.contains(&(x, y)) => palette.candidate,
+                    Pos::Visited(_) => palette.visited,
+                    Pos::NotVisited => palette.not_visited,
+                })
+                .collect::<Vec<_>>();
+
+            for _ in 0..scale {
+                for colour in &colours {
+                    for _ in 0..scale {
+                        pixels.extend_from_slice(colour);
+                    }
+                }
+            }
+        }
+
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    fn save(&self, path: &Path, format: ImageFormat) -> io::Result<()> {
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        match format {
+            ImageFormat::Ppm => {
+                write!(file, "P6\n{} {}\n255\n", self.width, self.height)?;
+                file.write_all(&self.pixels)?;
+            }
+            #[cfg(feature = "png")]
+            ImageFormat::Png => {
+                let mut encoder =
+                    png::Encoder::new(&mut file, self.width as u32, self.height as u32);
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder
+                    .write_header()
+                    .and_then(|mut writer| writer.write_image_data(&self.pixels))
+                    .map_err(io::Error::other)?;
+            }
+        }
+
+        file.flush()
+    }
+}
+
+// Timings and memory use of the grid on a generated size x size map
+pub fn benchmark(size: usize) -> String {
+    let grid = benchmark_grid(size);
+    let rule = MovementRule::default();
+    let diagonals = MovementRule {
+        diagonals: true,
+        ..rule
+    };
+
+    let mut report = format!("{size}x{size} map, grid uses {} bytes\n", grid.heap_bytes());
+
+    let start = Instant::now();
+    let mut traced = grid.clone();
+    trace_guard(&mut traced, rule).ok();
+    report += &format!(
+        "part 1: {} distinct positions in {:?}\n",
+        visited_positions(&traced),
+        start.elapsed()
+    );
+
+    for (name, rule) in [("part 2", rule), ("part 2 with diagonals", diagonals)] {
+        let start = Instant::now();
+        let obstacles = possible_obstacles_causing_a_loop(&mut grid.clone(), rule)
+            .map_or(0, |obstacles| obstacles.len());
+        report += &format!(
+            "{name}: {obstacles} obstacles causing a loop in {:?}\n",
+            start.elapsed()
+        );
+    }
+
+    let start = Instant::now();
+    let obstacles = changing_the_loop(&grid, rule).unwrap_or(0);
+    report += &format!(
+        "wrap-around: {obstacles} obstacles changing the loop in {:?}\n",
+        start.elapsed()
+    );
+
+    report
+}
+
+// The map with the longest walk out of a few random ones, so the guard has
+// some way to go
+fn benchmark_grid(size: usize) -> Grid {
+    let mut rng = XorShift::new();
+    let mut best = (0, Grid::new(0, 0));
+
+    for _ in 0..20 {
+        let mut text = String::new();
+        for y in 0..size {
+            for x in 0..size {
+                text.push(match rng.next() % 50 {
+                    _ if (x, y) == (size / 2, size / 2) => CHAR_UP,
+                    0 => CHAR_OBSTACLE,
+                    _ => CHAR_NOT_VISITED,
+                });
+            }
+            text.push('\n');
+        }
+
+        let grid = parse_text(&text, &Aliases::new()).unwrap_or(Grid::new(0, 0));
+        let mut traced = grid.clone();
+        if trace_guard(&mut traced, MovementRule::default()).is_ok() {
+            let walked = visited_positions(&traced);
+            if walked > best.0 {
+                best = (walked, grid);
+            }
+        }
+    }
+
+    best.1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Random maps with the guard facing any of the four main directions, from
+    // sparse to crowded with obstacles
+    fn random_grids() -> Vec<Grid> {
+        let mut rng = XorShift::new();
+        (0..200)
+            .map(|_| {
+                let width = 1 + rng.next() as usize % 24;
+                let height = 1 + rng.next() as usize % 24;
+                let density = 2 + rng.next() % 8;
+                let guard = (
+                    rng.next() as usize % width,
+                    rng.next() as usize % height,
+                    rng.letter("^>v<"),
+                );
+
+                let mut text = String::new();
+                for y in 0..height {
+                    for x in 0..width {
+                        text.push(match rng.next() % density {
+                            _ if (x, y) == (guard.0, guard.1) => guard.2,
+                            0 => CHAR_OBSTACLE,
+                            _ => CHAR_NOT_VISITED,
+                        });
+                    }
+                    text.push('\n');
+                }
+                parse_text(&text, &Aliases::new()).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn jump_table_finds_the_loops_of_the_walk() {
+        let turns = [Turn::Right, Turn::Left, Turn::Reverse, Turn::Alternate];
+
+        for grid in random_grids() {
+            let mut table = JumpTable::new(&grid);
+            for turn in turns {
+                let rule = MovementRule {
+                    turn,
+                    diagonals: false,
+                };
+                let guard = Guard::find(&grid, rule).unwrap();
+
+                // Every free position as the extra obstacle, and none at all
+                let free = grid
+                    .positions()
+                    .filter(|(_, _, pos)| *pos == Pos::NotVisited)
+                    .map(|(x, y, _)| Some((x, y)));
+                for extra in free.chain([None]) {
+                    let mut walked = grid.clone();
+                    walked.extra = extra;
+                    table.extra = extra;
+
+                    assert_eq!(
+                        table.causes_a_loop(&guard),
+                        matches!(
+                            trace_from(&mut walked, guard.clone()),
+                            Err(TraceError::Loop)
+                        ),
+                        "{turn:?} with an extra obstacle at {extra:?} on\n{grid}"
+                    );
+                }
+            }
+        }
+    }
+}
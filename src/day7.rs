@@ -1,13 +1,8 @@
 use std::fs::read_to_string;
 
-use itertools::{repeat_n, Itertools};
+use itertools::Itertools;
 
-// Normal function version, equivalent of python itertools product with repeat
-fn product<T>(v: &[T], repeat: usize) -> impl Iterator<Item = Vec<&T>> {
-    repeat_n(v, repeat).multi_cartesian_product()
-}
-
-// Macro version, equivalent of python itertools product with repeat
+// Equivalent of python itertools product with repeat
 macro_rules! product {
     ($it:expr, $rep:expr) => {
         vec![$it; $rep].into_iter().multi_cartesian_product()
@@ -31,7 +26,6 @@ fn parse_equation_inputs(filename: &str) -> Vec<EquationInput> {
             let res = parts.next()?.parse::<u64>().ok()?;
             let ops = parts
                 .next()?
-                .trim()
                 .split_whitespace()
                 .filter_map(|e| e.parse::<u64>().ok())
                 .collect_vec();
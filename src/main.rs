@@ -1,14 +1,73 @@
-use day7::Operator;
+use std::{env, fmt::Display, path::Path, slice, time::Duration};
 
-mod day1;
-mod day2;
-mod day3;
-mod day4;
-mod day5;
-mod day6;
-mod day7;
+use aoc24::{
+    day1, day2, day3,
+    day4::{self, OutputMode},
+    day5::{self, MiddlePolicy},
+    day6::{
+        self, Aliases, CollisionPolicy, Goal, ImageOptions, MovementRule, PlacementOptions, Turn,
+    },
+    day7::{self, Operator},
+};
+
+const USAGE: &str = "usage:
+    aoc24
+        print the solutions of all days
+    aoc24 day4 <part1|part2> <plain|ansi|json> [file]
+        show the matches of a day 4 part
+    aoc24 day4 bench [size]
+        compare the day 4 search engines on a generated grid
+    aoc24 day5 report [file | rules_file updates_file]
+        list the broken rules and corrected order of every day 5 update
+    aoc24 day5 bench [updates]
+        compare the day 5 rule lookups on generated rules and updates
+    aoc24 day6 bench [size]
+        time the day 6 parts and measure the grid's memory on a generated map
+    aoc24 day6 animate [file] [--delay <ms>] [--frames <dir>] [--alias <char>=<char>]...
+                       [--turn <right|left|reverse|alternate>] [--diagonals]
+        animate the day 6 guard in the terminal or write each step to a file in dir
+    aoc24 day6 loops [file] [index] [--alias <char>=<char>]...
+                     [--turn <right|left|reverse|alternate>] [--diagonals]
+        list the obstacles causing a day 6 loop or draw the walk with one of them
+    aoc24 day6 wrap [file] [--alias <char>=<char>]... [--turn <right|left|reverse|alternate>] [--diagonals]
+        walk a day 6 map where the guard comes back in on the other side when leaving
+    aoc24 day6 image <output> [file] [--scale <pixels>] [--every <steps>] [--candidates]
+                     [--format <ppm|png>] [--colour <kind>=<rrggbb>]... [--alias <char>=<char>]...
+                     [--turn <right|left|reverse|alternate>] [--diagonals]
+        draw the day 6 walk as an image, or every few steps into the output directory.
+        PNG (the default for outputs ending in .png) needs the png feature. Colour kinds
+        are not_visited, visited, obstacle, extra_obstacle, guard and candidate
+    aoc24 day6 optimise [file] [--obstacles <count>] [--min] [--beam <width>] [--alias <char>=<char>]...
+                        [--turn <right|left|reverse|alternate>] [--diagonals]
+        find where extra obstacles make the day 6 guard visit the most (or with --min the
+        fewest) distinct positions before leaving, keeping the best <width> placements
+    aoc24 day6 patrol [file] [--collisions <ignore|stop|obstacle>] [--alias <char>=<char>]...
+                      [--turn <right|left|reverse|alternate>] [--diagonals]
+        walk all guards of a day 6 map at the same time and draw their paths
+    --alias @=# reads every @ in a day 6 map as an obstacle";
 
 fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+    match args.as_slice() {
+        [] => all_days(),
+        ["day4", "bench", size @ ..] => day4_bench(size.first()),
+        ["day4", part, mode, file @ ..] => day4_output(part, mode, file.first()),
+        ["day5", "report", files @ ..] if files.len() <= 2 => day5_report(files),
+        ["day5", "bench", updates @ ..] => day5_bench(updates.first()),
+        ["day6", "bench", size @ ..] => day6_bench(size.first()),
+        ["day6", "animate", options @ ..] => day6_animate(options),
+        ["day6", "loops", options @ ..] => day6_loops(options),
+        ["day6", "optimise", options @ ..] => day6_optimise(options),
+        ["day6", "patrol", options @ ..] => day6_patrol(options),
+        ["day6", "wrap", options @ ..] => day6_wrap(options),
+        ["day6", "image", output, options @ ..] => day6_image(output, options),
+        _ => eprintln!("{USAGE}"),
+    }
+}
+
+fn all_days() {
     println!("Day 1 part 1: {}", day1::total_distance("data/day1.txt"));
     println!("Day 1 part 2: {}", day1::similarity_score("data/day1.txt"));
 
@@ -21,35 +80,37 @@ fn main() {
         day2::safe_reports("data/day2.txt", true)
     );
 
-    println!(
-        "Day 3 part 1: {}",
-        day3::sum_of_valid_muls("data/day3.txt", false)
+    print_result(
+        "Day 3 part 1",
+        day3::sum_of_valid_muls("data/day3.txt", false),
     );
-    println!(
-        "Day 3 part 2: {}",
-        day3::sum_of_valid_muls("data/day3.txt", true)
+    print_result(
+        "Day 3 part 2",
+        day3::sum_of_valid_muls("data/day3.txt", true),
     );
 
-    println!("Day 4 part 1: {}", day4::xmas_count("data/day4.txt"));
-    println!("Day 4 part 2: {}", day4::x_mas_count("data/day4.txt"));
+    print_result("Day 4 part 1", day4::xmas_count("data/day4.txt"));
+    print_result("Day 4 part 2", day4::x_mas_count("data/day4.txt"));
 
-    println!(
-        "Day 5 part 1: {}",
-        day5::sum_of_correct_update_middle_pages("data/day5rules.txt", "data/day5updates.txt")
+    let day5_input = day5_input(&[]);
+    print_result(
+        "Day 5 part 1",
+        day5::sum_of_correct_update_middle_pages(&day5_input, MiddlePolicy::Error),
     );
-    println!(
-        "Day 5 part 2: {}",
-        day5::sum_of_reordered_middle_pages("data/day5rules.txt", "data/day5updates.txt")
+    print_result(
+        "Day 5 part 2",
+        day5::sum_of_reordered_middle_pages(&day5_input, MiddlePolicy::Error),
     );
 
-    println!(
-        "Day 6 part 1: {}",
-        day6::distinct_positions("data/day6.txt")
+    let aliases = Aliases::new();
+    let rule = MovementRule::default();
+    print_result(
+        "Day 6 part 1",
+        day6::distinct_positions("data/day6.txt", &aliases, rule),
     );
-
-    println!(
-        "Day 6 part 2: {}",
-        day6::obstacles_causing_a_loop("data/day6.txt")
+    print_result(
+        "Day 6 part 2",
+        day6::obstacles_causing_a_loop("data/day6.txt", &aliases, rule),
     );
 
     println!(
@@ -65,3 +126,390 @@ fn main() {
         )
     );
 }
+
+fn day4_output(part: &str, mode: &str, file: Option<&&str>) {
+    let file = file.unwrap_or(&"data/day4.txt");
+    let mode = match mode {
+        "plain" => OutputMode::Plain,
+        "ansi" => OutputMode::Ansi,
+        "json" => OutputMode::Json,
+        _ => return eprintln!("{USAGE}"),
+    };
+
+    let output = match part {
+        "part1" => day4::xmas_output(file, mode),
+        "part2" => day4::x_mas_output(file, mode),
+        _ => return eprintln!("{USAGE}"),
+    };
+
+    match output {
+        Ok(output) => print!("{output}"),
+        Err(err) => eprintln!("error: {err}"),
+    }
+}
+
+fn day4_bench(size: Option<&&str>) {
+    match size.map_or(Ok(1000), |size| size.parse::<usize>()) {
+        Ok(size) => print!("{}", day4::benchmark(size)),
+        Err(_) => eprintln!("{USAGE}"),
+    }
+}
+
+// A single combined file, separate rules and updates files or the default files
+fn day5_input<'a>(files: &[&'a str]) -> day5::Input<'a> {
+    match files {
+        [file] => day5::Input::Combined(file),
+        [rules, updates, ..] => day5::Input::Split { rules, updates },
+        // Fall back to the older pre-split input files if there is no combined one
+        [] if Path::new("data/day5.txt").exists() => day5::Input::Combined("data/day5.txt"),
+        [] => day5::Input::Split {
+            rules: "data/day5rules.txt",
+            updates: "data/day5updates.txt",
+        },
+    }
+}
+
+fn day5_report(files: &[&str]) {
+    match day5::violation_report(&day5_input(files)) {
+        Ok(reports) => reports.iter().for_each(|report| print!("{report}")),
+        Err(err) => eprintln!("error: {err}"),
+    }
+}
+
+fn day5_bench(updates: Option<&&str>) {
+    match updates.map_or(Ok(100_000), |updates| updates.parse::<usize>()) {
+        Ok(updates) => print!("{}", day5::benchmark(updates)),
+        Err(_) => eprintln!("{USAGE}"),
+    }
+}
+
+fn day6_bench(size: Option<&&str>) {
+    match size.map_or(Ok(130), |size| size.parse::<usize>()) {
+        Ok(size) => print!("{}", day6::benchmark(size)),
+        Err(_) => eprintln!("{USAGE}"),
+    }
+}
+
+fn day6_animate(options: &[&str]) {
+    let mut delay = Duration::from_millis(50);
+    let mut frames = None;
+
+    let Some((file, aliases, rule)) = day6_map_options(options, |option, values| match option {
+        "--delay" => values
+            .next()
+            .and_then(|ms| ms.parse::<u64>().ok())
+            .map(|ms| delay = Duration::from_millis(ms))
+            .is_some(),
+        "--frames" => values.next().map(|&dir| frames = Some(dir)).is_some(),
+        _ => false,
+    }) else {
+        return eprintln!("{USAGE}");
+    };
+
+    let summary = match frames {
+        Some(dir) => day6::write_frames(file, &aliases, rule, Path::new(dir)),
+        None => day6::animate(file, &aliases, rule, delay),
+    };
+
+    match summary {
+        Ok(summary) => print!("{summary}"),
+        Err(err) => eprintln!("error: {err}"),
+    }
+}
+
+fn day6_loops(options: &[&str]) {
+    let Some(Day6Options {
+        aliases,
+        rule,
+        positional,
+    }) = day6_options(options, |_, _| false)
+    else {
+        return eprintln!("{USAGE}");
+    };
+
+    // A trailing number picks the obstacle to draw, anything else is the file
+    let (file, index) = match positional.as_slice() {
+        [] => ("data/day6.txt", None),
+        [file, index] => (*file, Some(*index)),
+        [option] if option.parse::<usize>().is_ok() => ("data/day6.txt", Some(*option)),
+        [file] => (*file, None),
+        _ => return eprintln!("{USAGE}"),
+    };
+
+    let obstacles = match day6::loop_obstacles(file, &aliases, rule) {
+        Ok(obstacles) => obstacles,
+        Err(err) => return eprintln!("error: {err}"),
+    };
+
+    let Some(index) = index else {
+        for (i, obstacle) in obstacles.iter().enumerate() {
+            println!(
+                "{i}: obstacle at ({}, {}), loop of {} states",
+                obstacle.x,
+                obstacle.y,
+                obstacle.cycle.len()
+            );
+        }
+        return;
+    };
+
+    let Some(obstacle) = index.parse::<usize>().ok().and_then(|i| obstacles.get(i)) else {
+        return eprintln!(
+            "error: no loop obstacle {index}, there are {}",
+            obstacles.len()
+        );
+    };
+
+    match day6::loop_obstacle_grid(file, &aliases, rule, obstacle.x, obstacle.y) {
+        Ok(grid) => print!("{grid}"),
+        Err(err) => return eprintln!("error: {err}"),
+    }
+
+    let cycle = obstacle.cycle.iter().map(|state| state.to_string());
+    println!("loop: {}", cycle.collect::<Vec<_>>().join(" -> "));
+}
+
+fn day6_optimise(options: &[&str]) {
+    let mut placement = PlacementOptions::default();
+
+    let Some((file, aliases, rule)) = day6_map_options(options, |option, values| match option {
+        "--obstacles" => values
+            .next()
+            .and_then(|count| count.parse().ok())
+            .filter(|&count| count > 0)
+            .map(|count| placement.obstacles = count)
+            .is_some(),
+        "--min" => {
+            placement.goal = Goal::Minimise;
+            true
+        }
+        "--beam" => values
+            .next()
+            .and_then(|width| width.parse().ok())
+            .filter(|&width| width > 0)
+            .map(|width| placement.beam_width = width)
+            .is_some(),
+        _ => false,
+    }) else {
+        return eprintln!("{USAGE}");
+    };
+
+    match day6::optimise_placement(file, &aliases, rule, &placement) {
+        Ok(report) => print!("{report}"),
+        Err(err) => eprintln!("error: {err}"),
+    }
+}
+
+fn day6_patrol(options: &[&str]) {
+    let mut policy = CollisionPolicy::Ignore;
+
+    let Some((file, aliases, rule)) =
+        day6_map_options(options, |option, values| match (option, values.next()) {
+            ("--collisions", Some(&"ignore")) => {
+                policy = CollisionPolicy::Ignore;
+                true
+            }
+            ("--collisions", Some(&"stop")) => {
+                policy = CollisionPolicy::Stop;
+                true
+            }
+            ("--collisions", Some(&"obstacle")) => {
+                policy = CollisionPolicy::Obstacle;
+                true
+            }
+            _ => false,
+        })
+    else {
+        return eprintln!("{USAGE}");
+    };
+
+    match day6::patrol_map(file, &aliases, rule, policy) {
+        Ok((summary, grid)) => print!("{grid}{summary}"),
+        Err(err) => eprintln!("error: {err}"),
+    }
+}
+
+fn day6_wrap(options: &[&str]) {
+    let Some((file, aliases, rule)) = day6_map_options(options, |_, _| false) else {
+        return eprintln!("{USAGE}");
+    };
+
+    print_result("Loop", day6::wrapped_loop(file, &aliases, rule));
+    print_result(
+        "Obstacles changing the loop length",
+        day6::obstacles_changing_the_loop(file, &aliases, rule),
+    );
+}
+
+fn day6_image(output: &str, options: &[&str]) {
+    let mut image = ImageOptions::default();
+    let mut every = None;
+    let mut png = output.ends_with(".png");
+
+    let Some((file, aliases, rule)) = day6_map_options(options, |option, values| match option {
+        "--scale" => values
+            .next()
+            .and_then(|scale| scale.parse().ok())
+            .map(|scale| image.scale = scale)
+            .is_some(),
+        "--every" => values
+            .next()
+            .and_then(|steps| steps.parse().ok())
+            .map(|steps| every = Some(steps))
+            .is_some(),
+        "--candidates" => {
+            image.candidates = true;
+            true
+        }
+        "--format" => match values.next() {
+            Some(&"ppm") => {
+                png = false;
+                true
+            }
+            Some(&"png") => {
+                png = true;
+                true
+            }
+            _ => false,
+        },
+        "--colour" => day6_colour(&mut image.palette, values.next()),
+        _ => false,
+    }) else {
+        return eprintln!("{USAGE}");
+    };
+
+    if png {
+        #[cfg(feature = "png")]
+        {
+            image.format = day6::ImageFormat::Png;
+        }
+        #[cfg(not(feature = "png"))]
+        return eprintln!("error: PNG output needs the png feature");
+    }
+
+    let summary = match every {
+        Some(every) => {
+            day6::write_image_frames(file, &aliases, rule, &image, Path::new(output), every)
+        }
+        None => day6::write_image(file, &aliases, rule, &image, Path::new(output)),
+    };
+
+    match summary {
+        Ok(summary) => print!("{summary}"),
+        Err(err) => eprintln!("error: {err}"),
+    }
+}
+
+// The options every day 6 subcommand takes, and the arguments that aren't
+// options (usually the map file)
+struct Day6Options<'a> {
+    aliases: Aliases,
+    rule: MovementRule,
+    positional: Vec<&'a str>,
+}
+
+// Parse --alias, --turn and --diagonals. Other options go to own, which reads
+// their value from the remaining arguments and returns false if the option or
+// the value is invalid. None if any option is invalid.
+fn day6_options<'a>(
+    options: &[&'a str],
+    mut own: impl FnMut(&'a str, &mut slice::Iter<&'a str>) -> bool,
+) -> Option<Day6Options<'a>> {
+    let mut parsed = Day6Options {
+        aliases: Aliases::new(),
+        rule: MovementRule::default(),
+        positional: Vec::new(),
+    };
+
+    let mut options = options.iter();
+    while let Some(&option) = options.next() {
+        match option {
+            "--alias" => {
+                if !day6_alias(&mut parsed.aliases, options.next()) {
+                    return None;
+                }
+            }
+            "--turn" => parsed.rule.turn = day6_turn(options.next())?,
+            "--diagonals" => parsed.rule.diagonals = true,
+            _ if option.starts_with("--") => {
+                if !own(option, &mut options) {
+                    return None;
+                }
+            }
+            _ => parsed.positional.push(option),
+        }
+    }
+
+    Some(parsed)
+}
+
+// day6_options for the subcommands that take at most one map file, by default
+// the puzzle input
+fn day6_map_options<'a>(
+    options: &[&'a str],
+    own: impl FnMut(&'a str, &mut slice::Iter<&'a str>) -> bool,
+) -> Option<(&'a str, Aliases, MovementRule)> {
+    let Day6Options {
+        aliases,
+        rule,
+        positional,
+    } = day6_options(options, own)?;
+
+    match positional.as_slice() {
+        [] => Some(("data/day6.txt", aliases, rule)),
+        [file] => Some((file, aliases, rule)),
+        _ => None,
+    }
+}
+
+// Set a palette colour given as "<kind>=<rrggbb>", false if it's not in that form
+fn day6_colour(palette: &mut day6::Palette, spec: Option<&&str>) -> bool {
+    let Some((kind, hex)) = spec.and_then(|spec| spec.split_once('=')) else {
+        return false;
+    };
+    let colour = match kind {
+        "not_visited" => &mut palette.not_visited,
+        "visited" => &mut palette.visited,
+        "obstacle" => &mut palette.obstacle,
+        "extra_obstacle" => &mut palette.extra_obstacle,
+        "guard" => &mut palette.guard,
+        "candidate" => &mut palette.candidate,
+        _ => return false,
+    };
+
+    match u32::from_str_radix(hex.trim_start_matches('#'), 16) {
+        Ok(rgb) if hex.trim_start_matches('#').len() == 6 => {
+            let [_, r, g, b] = rgb.to_be_bytes();
+            *colour = [r, g, b];
+            true
+        }
+        _ => false,
+    }
+}
+
+fn day6_turn(name: Option<&&str>) -> Option<Turn> {
+    match name {
+        Some(&"right") => Some(Turn::Right),
+        Some(&"left") => Some(Turn::Left),
+        Some(&"reverse") => Some(Turn::Reverse),
+        Some(&"alternate") => Some(Turn::Alternate),
+        _ => None,
+    }
+}
+
+// Add an alias given as "<alias>=<char>", false if it's not in that form
+fn day6_alias(aliases: &mut Aliases, spec: Option<&&str>) -> bool {
+    let mut chars = spec.map_or("", |spec| spec).chars();
+    match (chars.next(), chars.next(), chars.next(), chars.next()) {
+        (Some(alias), Some('='), Some(target), None) => aliases.insert(alias, target).is_ok(),
+        _ => false,
+    }
+}
+
+// Print the result of a puzzle part or the error that prevented solving it
+fn print_result<T: Display, E: Display>(label: &str, result: Result<T, E>) {
+    match result {
+        Ok(value) => println!("{label}: {value}"),
+        Err(err) => println!("{label}: error: {err}"),
+    }
+}
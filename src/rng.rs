@@ -0,0 +1,28 @@
+// Deterministic xorshift generator, good enough for benchmark inputs
+pub struct XorShift(u64);
+
+impl XorShift {
+    pub fn new() -> XorShift {
+        XorShift(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn letter(&mut self, letters: &str) -> char {
+        let letters = letters.as_bytes();
+        letters[(self.next() % letters.len() as u64) as usize] as char
+    }
+
+    // Fisher-Yates shuffle
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}